@@ -0,0 +1,587 @@
+use crate::bundle_cache::BundleCache;
+use crate::conductor_installer::ConductorInstaller;
+use crate::integrity::BundleVerifier;
+use crate::mem_proof::{JoiningCodeClient, MembraneProofProvider, ReadOnlyMemProofs};
+use crate::retry::{HttpStatusError, RetrySettings, Retrying};
+use crate::settings::{HposApiEndpoint, InstallBackend, Settings};
+use crate::types::{
+    happ::{HappPreferences, InstallHappBody, InstallHappResponse, PublishedHappPreferences},
+    hbs::{HbsClient, HostCredentials},
+    HappBundle,
+};
+use crate::utils::{get_all_published_hosted_happs, get_holoport_id};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use futures::future::{FutureExt, LocalBoxFuture};
+use holochain_conductor_api::AppStatusFilter;
+use holochain_types::dna::ActionHashB64;
+use holochain_types::prelude::{
+    AgentPubKey, AppBundleSource, DnaModifiersOpt, InstallAppPayload, MembraneProof, RoleSettings,
+    Signature, YamlProperties,
+};
+use hpos_hc_connect::{
+    hha_agent::CoreAppAgent, holo_config::Config, holofuel_types::PendingTransaction,
+    AdminWebsocket,
+};
+use isahc::config::{Configurable, Dial};
+use isahc::{AsyncReadResponseExt, HttpClient, Request};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, trace};
+use url::Url;
+
+/// Open connections to hbs, the core app and the conductor's admin interface,
+/// whose calls are retried and timed out as set in `Settings`
+pub(crate) struct Connections {
+    pub hbs: HbsClient,
+    pub core_app: Retrying<CoreApp>,
+    pub admin_websocket: Retrying<AdminWebsocket>,
+}
+
+impl Connections {
+    pub async fn open(config: &Config, settings: &Settings) -> Result<Self> {
+        let Settings {
+            retry, timeouts, ..
+        } = settings;
+        let hbs = HbsClient::connect()?
+            .with_retry(retry.clone())
+            .with_timeout(timeouts.http)
+            .with_url(settings.hbs_url.clone())
+            .with_hpos_config_path(settings.hpos_config_path.clone());
+
+        let core_app = connect_core_app(config, settings).await?;
+        trace!("Connected to core app interface.");
+
+        let admin_websocket = connect_admin_websocket(config, retry, timeouts.conductor).await?;
+
+        Ok(Connections {
+            hbs,
+            core_app: Retrying::new(core_app, retry.clone(), timeouts.hha)
+                .with_reconnect(reconnect_core_app(config, settings)),
+            admin_websocket: retrying_admin_websocket(admin_websocket, config, settings),
+        })
+    }
+}
+
+/// Opens a new connection to the core app, for `Retrying` to replace one that broke
+fn reconnect_core_app(
+    config: &Config,
+    settings: &Settings,
+) -> impl Fn() -> LocalBoxFuture<'static, Result<CoreApp>> {
+    let config = config.clone();
+    let hpos_config_path = settings.hpos_config_path.clone();
+    move || {
+        let config = config.clone();
+        let hpos_config_path = hpos_config_path.clone();
+        async move {
+            Ok(CoreApp {
+                agent: CoreAppAgent::spawn(Some(&config)).await?,
+                hpos_config_path,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// Times out and retries the calls made through `admin_websocket` as set in `settings`,
+/// connecting to the admin interface again after a connection error
+fn retrying_admin_websocket(
+    admin_websocket: AdminWebsocket,
+    config: &Config,
+    settings: &Settings,
+) -> Retrying<AdminWebsocket> {
+    let admin_port = config.admin_port;
+    Retrying::new(
+        admin_websocket,
+        settings.retry.clone(),
+        settings.timeouts.conductor,
+    )
+    .with_reconnect(move || {
+        async move { Ok(AdminWebsocket::connect(admin_port).await?) }.boxed_local()
+    })
+}
+
+async fn connect_core_app(config: &Config, settings: &Settings) -> Result<CoreApp> {
+    let agent = settings
+        .retry
+        .retry_with_timeout(
+            "connecting to the core app",
+            settings.timeouts.hha,
+            move || async move { Ok(CoreAppAgent::spawn(Some(config)).await?) },
+        )
+        .await?;
+    Ok(CoreApp {
+        agent,
+        hpos_config_path: settings.hpos_config_path.clone(),
+    })
+}
+
+async fn connect_admin_websocket(
+    config: &Config,
+    retry: &RetrySettings,
+    timeout: Duration,
+) -> Result<AdminWebsocket> {
+    retry
+        .retry_with_timeout(
+            "connecting to holochain's admin interface",
+            timeout,
+            move || async move { Ok(AdminWebsocket::connect(config.admin_port).await?) },
+        )
+        .await
+        .context("failed to connect to holochain's admin interface")
+}
+
+/// The holochain conductor admin calls the installer makes
+#[async_trait(?Send)]
+pub trait ConductorAdmin {
+    /// Lists the `installed_app_id`s of all apps enabled in the conductor
+    async fn list_enabled_app_ids(&mut self) -> Result<Vec<String>>;
+    /// Lists the `installed_app_id`s of all apps disabled, or paused after an error, in the conductor
+    async fn list_disabled_app_ids(&mut self) -> Result<Vec<String>>;
+    /// Holochain-disables an installed app
+    async fn disable_app(&mut self, installed_app_id: &str) -> Result<()>;
+    /// Uninstalls an app (without forcing) from the conductor
+    async fn uninstall_app(&mut self, installed_app_id: &str) -> Result<()>;
+    /// Installs an app from a local bundle, leaving it disabled
+    async fn install_app(&mut self, app: AppInstall) -> Result<()>;
+    /// Holochain-enables an installed app
+    async fn enable_app(&mut self, installed_app_id: &str) -> Result<()>;
+}
+
+/// An app to install in the conductor from a local bundle
+#[derive(Debug, Clone)]
+pub struct AppInstall {
+    pub installed_app_id: String,
+    pub bundle_path: PathBuf,
+    pub agent_key: AgentPubKey,
+    /// Keyed by role name
+    pub membrane_proofs: HashMap<String, MembraneProof>,
+    /// DNA properties given to every role, overriding those in the bundle
+    pub properties: Option<serde_yaml::Value>,
+}
+
+#[async_trait(?Send)]
+impl ConductorAdmin for AdminWebsocket {
+    async fn list_enabled_app_ids(&mut self) -> Result<Vec<String>> {
+        let enabled_happs = self.list_apps(Some(AppStatusFilter::Enabled)).await?;
+        Ok(enabled_happs
+            .into_iter()
+            .map(|h| h.installed_app_id)
+            .collect())
+    }
+
+    async fn list_disabled_app_ids(&mut self) -> Result<Vec<String>> {
+        let mut disabled_happs = self.list_apps(Some(AppStatusFilter::Disabled)).await?;
+        disabled_happs.extend(self.list_apps(Some(AppStatusFilter::Paused)).await?);
+        Ok(disabled_happs
+            .into_iter()
+            .map(|h| h.installed_app_id)
+            .collect())
+    }
+
+    async fn disable_app(&mut self, installed_app_id: &str) -> Result<()> {
+        AdminWebsocket::disable_app(self, installed_app_id).await?;
+        Ok(())
+    }
+
+    async fn uninstall_app(&mut self, installed_app_id: &str) -> Result<()> {
+        AdminWebsocket::uninstall_app(self, installed_app_id, false).await?;
+        Ok(())
+    }
+
+    async fn install_app(&mut self, app: AppInstall) -> Result<()> {
+        let AppInstall {
+            installed_app_id,
+            bundle_path,
+            agent_key,
+            membrane_proofs,
+            properties,
+        } = app;
+        let modifiers = properties.map(|properties| DnaModifiersOpt {
+            properties: Some(YamlProperties::new(properties)),
+            ..DnaModifiersOpt::none()
+        });
+        let roles_settings = membrane_proofs
+            .into_iter()
+            .map(|(role_name, membrane_proof)| {
+                (
+                    role_name,
+                    RoleSettings::Provisioned {
+                        membrane_proof: Some(membrane_proof),
+                        modifiers: modifiers.clone(),
+                    },
+                )
+            })
+            .collect();
+        let payload = InstallAppPayload {
+            source: AppBundleSource::Path(bundle_path),
+            agent_key: Some(agent_key),
+            installed_app_id: Some(installed_app_id),
+            network_seed: None,
+            roles_settings: Some(roles_settings),
+            ignore_genesis_failure: false,
+            allow_throwaway_random_agent_key: false,
+        };
+        AdminWebsocket::install_app(self, payload).await?;
+        Ok(())
+    }
+
+    async fn enable_app(&mut self, installed_app_id: &str) -> Result<()> {
+        AdminWebsocket::enable_app(self, installed_app_id).await?;
+        Ok(())
+    }
+}
+
+/// The hha (holo hosting app) calls the installer makes through the core app
+#[async_trait(?Send)]
+pub trait HostingRegistry {
+    /// Gets all the happs published for hosting
+    async fn get_published_happs(&mut self) -> Result<Vec<HappBundle>>;
+    /// Gets the provider who published a happ, and the prices and invoicing terms they set for hosting it
+    async fn get_happ_preferences(
+        &mut self,
+        happ_id: &ActionHashB64,
+    ) -> Result<PublishedHappPreferences>;
+    /// Gets the jurisdiction of a happ provider, if they have one registered
+    async fn get_publisher_jurisdiction(
+        &mut self,
+        publisher: &AgentPubKey,
+    ) -> Result<Option<String>>;
+    /// Gets the hosting preferences the host set
+    async fn get_host_preferences(&mut self) -> Result<HappPreferences>;
+    /// Gets the host's pending holofuel transactions, which include its hosting invoices
+    async fn get_pending_transactions(&mut self) -> Result<PendingTransaction>;
+    /// Holo-disables a happ for this holoport
+    async fn holo_disable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()>;
+    /// Holo-enables a happ for this holoport
+    async fn holo_enable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()>;
+    /// Gets the host agent's pubkey, which read-only instances of hosted happs are installed with
+    async fn get_host_agent_pubkey(&mut self) -> Result<AgentPubKey>;
+    /// Signs `payload` with the host agent's key (eg: a request for membrane proofs)
+    async fn sign_raw(&mut self, payload: Vec<u8>) -> Result<Signature>;
+}
+
+/// The core app agent hha is called through, with the hpos config the holoport id is derived from
+pub struct CoreApp {
+    agent: CoreAppAgent,
+    /// Read from the `HPOS_CONFIG_PATH` env var if unset
+    hpos_config_path: Option<PathBuf>,
+}
+
+#[async_trait(?Send)]
+impl HostingRegistry for CoreApp {
+    async fn get_published_happs(&mut self) -> Result<Vec<HappBundle>> {
+        get_all_published_hosted_happs(&mut self.agent).await
+    }
+
+    async fn get_happ_preferences(
+        &mut self,
+        happ_id: &ActionHashB64,
+    ) -> Result<PublishedHappPreferences> {
+        let happ_prefs = self.agent.get_happ_preferences(happ_id.clone()).await?;
+        Ok(happ_prefs.into())
+    }
+
+    async fn get_publisher_jurisdiction(
+        &mut self,
+        publisher: &AgentPubKey,
+    ) -> Result<Option<String>> {
+        let jurisdiction = self
+            .agent
+            .get_publisher_jurisdiction(publisher.clone())
+            .await?;
+        Ok(jurisdiction)
+    }
+
+    async fn get_host_preferences(&mut self) -> Result<HappPreferences> {
+        let host_preferences = self.agent.get_host_preferences().await?;
+        Ok(host_preferences.into())
+    }
+
+    async fn get_pending_transactions(&mut self) -> Result<PendingTransaction> {
+        let pending_transactions = self.agent.get_pending_transactions().await?;
+        Ok(pending_transactions)
+    }
+
+    async fn holo_disable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()> {
+        let holoport_id = get_holoport_id(self.hpos_config_path.as_deref()).await?;
+        self.agent.holo_disable_happ(happ_id, &holoport_id).await?;
+        Ok(())
+    }
+
+    async fn holo_enable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()> {
+        let holoport_id = get_holoport_id(self.hpos_config_path.as_deref()).await?;
+        self.agent.holo_enable_happ(happ_id, &holoport_id).await?;
+        Ok(())
+    }
+
+    async fn get_host_agent_pubkey(&mut self) -> Result<AgentPubKey> {
+        Ok(self.agent.pubkey().await?)
+    }
+
+    async fn sign_raw(&mut self, payload: Vec<u8>) -> Result<Signature> {
+        Ok(self.agent.sign_raw(payload.into()).await?)
+    }
+}
+
+/// The hbs calls the installer makes
+#[async_trait(?Send)]
+pub trait HostBackend {
+    /// Gets the kyc level and jurisdiction of the host
+    async fn get_host_credentials(&self) -> Result<HostCredentials>;
+}
+
+#[async_trait(?Send)]
+impl HostBackend for HbsClient {
+    async fn get_host_credentials(&self) -> Result<HostCredentials> {
+        match self.get_host_hosting_criteria().await {
+            Some(v) => Ok(v),
+            None => {
+                error!("Unable to get hosting criteria from HBS. Exiting...");
+                Err(anyhow!("Unable to get hosting criteria"))
+            }
+        }
+    }
+}
+
+/// Installs, holochain-enables and holo-enables the read-only instance of a hosted happ
+#[async_trait(?Send)]
+pub trait HostedInstaller {
+    /// `dna_hashes` are the DNA hashes registered for the happ in hha, if any.
+    /// A happ with a `special_installed_app_id` runs on that core app, so only its servicelogger is installed.
+    async fn install_hosted_happ(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+        dna_hashes: &[String],
+        special_installed_app_id: Option<&str>,
+    ) -> Result<()>;
+}
+
+/// Installs hosted happs through hpos-api's `/api/v2/apps/hosted/install` endpoint.
+/// hpos-api downloads the bundle itself, so the bundle verified here is only the one the CDN served us,
+/// and this backend cannot guarantee that the bytes it installs are the verified ones. Use the conductor backend for that.
+#[derive(Clone)]
+pub struct HposApiInstaller {
+    client: HttpClient,
+    install_url: Url,
+    authorization: Option<String>,
+    mem_proofs: Arc<dyn MembraneProofProvider>,
+    verifier: BundleVerifier,
+    retry: RetrySettings,
+}
+
+impl HposApiInstaller {
+    pub fn new(
+        endpoint: &HposApiEndpoint,
+        mem_proofs: Arc<dyn MembraneProofProvider>,
+        verifier: BundleVerifier,
+        retry: RetrySettings,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let mut client = HttpClient::builder().timeout(timeout);
+        if let Some(socket_path) = &endpoint.socket_path {
+            let dial: Dial = format!("unix:{}", socket_path.display())
+                .parse()
+                .map_err(|e| {
+                    anyhow!("invalid hpos-api socket {}: {:?}", socket_path.display(), e)
+                })?;
+            client = client.dial(dial);
+        }
+
+        Ok(HposApiInstaller {
+            client: client.build()?,
+            install_url: endpoint.base_url.join("api/v2/apps/hosted/install")?,
+            authorization: endpoint.authorization.clone(),
+            mem_proofs,
+            verifier,
+            retry,
+        })
+    }
+
+    /// Gets membrane proofs from the joining-code service if one is configured, and uses read-only proofs otherwise.
+    /// Requests to the joining-code service are signed through `core_app`.
+    pub fn from_settings(
+        settings: &Settings,
+        cache: Arc<BundleCache>,
+        core_app: &Retrying<CoreApp>,
+    ) -> Result<Self> {
+        let (mem_proofs, verifier) = install_dependencies(settings, cache, core_app);
+        HposApiInstaller::new(
+            &settings.hpos_api,
+            mem_proofs,
+            verifier,
+            settings.retry.clone(),
+            settings.timeouts.http,
+        )
+    }
+}
+
+/// The membrane proof provider and bundle verifier configured in `settings`, both reading bundles from `cache`.
+/// The joining-code client signs its requests through `core_app`, the run's connection to the core app.
+fn install_dependencies(
+    settings: &Settings,
+    cache: Arc<BundleCache>,
+    core_app: &Retrying<CoreApp>,
+) -> (Arc<dyn MembraneProofProvider>, BundleVerifier) {
+    let verifier = BundleVerifier::new(cache.clone(), settings.pinned_bundle_hashes.clone());
+    let mem_proofs: Arc<dyn MembraneProofProvider> = match &settings.joining_code_url {
+        Some(url) => Arc::new(
+            JoiningCodeClient::new(url.clone(), cache.clone(), core_app.clone())
+                .with_timeout(settings.timeouts.http)
+                .with_retry(settings.retry.clone()),
+        ),
+        None => Arc::new(ReadOnlyMemProofs::new(cache)),
+    };
+    (mem_proofs, verifier)
+}
+
+#[async_trait(?Send)]
+impl HostedInstaller for HposApiInstaller {
+    async fn install_hosted_happ(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+        dna_hashes: &[String],
+        _special_installed_app_id: Option<&str>,
+    ) -> Result<()> {
+        // hpos-api takes no bundle, so this only refuses happs whose bundle on the CDN is already swapped
+        self.verifier
+            .verify(happ_id, bundle_url, dna_hashes)
+            .await
+            .with_context(|| format!("refusing to install happ {}", happ_id))?;
+
+        trace!("Load mem-proofs for {}", happ_id);
+        let mem_proof: HashMap<String, MembraneProof> = self
+            .mem_proofs
+            .get_membrane_proofs(happ_id, bundle_url)
+            .await
+            .with_context(|| format!("failed to get membrane proofs for happ {}", happ_id))?;
+        trace!(
+            "Installing happ-id {} with mem_proof {:?}",
+            happ_id,
+            mem_proof
+        );
+
+        // The installation implementation can be found in`hpos-api` here: https://github.com/Holo-Host/hpos-api-rust/blob/develop/src/handlers/install/mod.rs#L31
+        // NB: The `/install_hosted_happ` endpoint will holo-enable the app if it is already installed and enabled on hololchain,
+        // ...otherwise it takes the following 5 steps:
+        // 1. installs sl for the app,
+        // 2. holochain-enables the app's sl,
+        // 3. installs the app on holochain (NB: The app installs with the host agent pubkey as this a read-only instance),
+        // 4. holochain-enables the app,
+        // 5. holo-enables the app
+        let body = InstallHappBody {
+            happ_id: happ_id.to_string(),
+            membrane_proofs: mem_proof.clone(),
+        };
+        let body = serde_json::to_vec(&body)?;
+        // A request that timed out may still be installing the happ, so only failures that show it was not sent,
+        // or that hpos-api could not take it (eg: a 503), are retried
+        self.retry
+            .retry_change("hpos-api install", || self.post_install(&body))
+            .await
+            .with_context(|| format!("hpos-api failed to install happ {}", happ_id))?;
+        info!("Installed and enabled happ-id {}", happ_id);
+        Ok(())
+    }
+}
+
+impl HposApiInstaller {
+    async fn post_install(&self, body: &[u8]) -> Result<()> {
+        let mut request =
+            Request::post(self.install_url.as_str()).header("Content-Type", "application/json");
+        if let Some(authorization) = &self.authorization {
+            request = request.header("Authorization", authorization);
+        }
+        let mut response = self
+            .client
+            .send_async(request.body(body.to_vec())?)
+            .await
+            .with_context(|| format!("failed to reach hpos-api at {}", self.install_url))?;
+        let status = response.status().as_u16();
+        let response_body = response.text().await?;
+        trace!(
+            "`/v2/apps/hosted/install` happ response {}: {}",
+            status,
+            response_body
+        );
+
+        match InstallHappResponse::parse(status, &response_body) {
+            InstallHappResponse::Installed => Ok(()),
+            InstallHappResponse::Rejected { status, message } => {
+                Err(HttpStatusError { status, message }.into())
+            }
+        }
+    }
+}
+
+/// The hosted installer selected by `Settings::install_backend`
+pub enum Installer {
+    HposApi(HposApiInstaller),
+    Conductor(ConductorInstaller<Retrying<AdminWebsocket>, Retrying<CoreApp>>),
+}
+
+impl Installer {
+    /// Sets up the selected installer, opening its own connection to the conductor if it needs one.
+    /// Bundles are read from `cache`, and hha is called through `core_app`, both of which should be shared with the rest of the run.
+    pub async fn open(
+        config: &Config,
+        settings: &Settings,
+        cache: Arc<BundleCache>,
+        core_app: &Retrying<CoreApp>,
+    ) -> Result<Self> {
+        match settings.install_backend {
+            InstallBackend::HposApi => Ok(Installer::HposApi(HposApiInstaller::from_settings(
+                settings, cache, core_app,
+            )?)),
+            InstallBackend::Conductor => {
+                let servicelogger_bundle_url =
+                    settings.servicelogger_bundle_url.clone().ok_or_else(|| {
+                        anyhow!(
+                            "installing through the conductor requires a servicelogger bundle url"
+                        )
+                    })?;
+                let (mem_proofs, verifier) =
+                    install_dependencies(settings, cache.clone(), core_app);
+                let timeouts = &settings.timeouts;
+                let admin_websocket =
+                    connect_admin_websocket(config, &settings.retry, timeouts.conductor).await?;
+                Ok(Installer::Conductor(ConductorInstaller::new(
+                    retrying_admin_websocket(admin_websocket, config, settings),
+                    core_app.clone(),
+                    cache,
+                    mem_proofs,
+                    verifier,
+                    servicelogger_bundle_url,
+                )))
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl HostedInstaller for Installer {
+    async fn install_hosted_happ(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+        dna_hashes: &[String],
+        special_installed_app_id: Option<&str>,
+    ) -> Result<()> {
+        match self {
+            Installer::HposApi(installer) => {
+                installer
+                    .install_hosted_happ(happ_id, bundle_url, dna_hashes, special_installed_app_id)
+                    .await
+            }
+            Installer::Conductor(installer) => {
+                installer
+                    .install_hosted_happ(happ_id, bundle_url, dna_hashes, special_installed_app_id)
+                    .await
+            }
+        }
+    }
+}
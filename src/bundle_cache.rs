@@ -0,0 +1,453 @@
+use crate::settings::Settings;
+use crate::timeout::TimeoutSettings;
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tokio::sync::Mutex;
+use tracing::{debug, trace, warn};
+use url::Url;
+
+pub const DEFAULT_BUNDLE_CACHE_DIR: &str = "/var/lib/holo-auto-installer/bundles";
+pub const DEFAULT_BUNDLE_CACHE_SIZE_MIB: u64 = 1024;
+const INDEX_FILE_NAME: &str = "index.json";
+
+/// A cached download of one bundle url
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    /// ETag the server sent with the bundle, used to revalidate it
+    etag: Option<String>,
+    /// Hex encoded sha256 of the bundle, which is also its file name in the cache
+    sha256: String,
+    size: u64,
+    /// Unix time in milliseconds at which the bundle was last used, for eviction
+    last_used: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    /// Keyed by bundle url
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+/// Local copies of happ bundles, keyed by bundle url and content hash.
+/// A cached bundle is revalidated with its ETag, and used as is when the bundle url cannot be reached.
+/// Least recently used bundles are evicted once the cache grows past its size limit, except for those in use.
+/// One cache is built per run and shared through an `Arc`, so that every reader and writer of the index takes the same lock.
+#[derive(Debug)]
+pub struct BundleCache {
+    dir: PathBuf,
+    max_size: u64,
+    client: reqwest::Client,
+    /// How long downloading one bundle may take
+    download_timeout: Duration,
+    /// Serializes reads and writes of the index
+    index_lock: Mutex<()>,
+    /// How many `CachedBundle`s of each bundle, by sha256, are held and must not be evicted
+    pinned: std::sync::Mutex<HashMap<String, usize>>,
+}
+
+/// A bundle read through the cache. It is not evicted until this is dropped,
+/// so it should be held until whatever reads the bundle file (eg: an install) has finished.
+#[derive(Debug)]
+pub struct CachedBundle<'a> {
+    path: PathBuf,
+    /// The cache and sha256 the bundle is pinned under, `None` for file urls, which the cache does not manage
+    pin: Option<(&'a BundleCache, String)>,
+}
+
+impl CachedBundle<'_> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CachedBundle<'_> {
+    fn drop(&mut self) {
+        if let Some((cache, sha256)) = &self.pin {
+            cache.unpin(sha256);
+        }
+    }
+}
+
+impl Default for BundleCache {
+    fn default() -> Self {
+        BundleCache::new(
+            PathBuf::from(DEFAULT_BUNDLE_CACHE_DIR),
+            DEFAULT_BUNDLE_CACHE_SIZE_MIB * 1024 * 1024,
+        )
+    }
+}
+
+impl BundleCache {
+    /// A cache in `dir` holding at most `max_size` bytes of bundles
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        BundleCache {
+            dir,
+            max_size,
+            client: reqwest::Client::new(),
+            download_timeout: TimeoutSettings::default().download,
+            index_lock: Default::default(),
+            pinned: Default::default(),
+        }
+    }
+
+    /// The cache configured in `settings`
+    pub fn from_settings(settings: &Settings) -> Self {
+        BundleCache::new(
+            settings.bundle_cache_dir.clone(),
+            settings.bundle_cache_size_mib * 1024 * 1024,
+        )
+        .with_download_timeout(settings.timeouts.download)
+    }
+
+    pub fn with_download_timeout(self, download_timeout: Duration) -> Self {
+        Self {
+            download_timeout,
+            ..self
+        }
+    }
+
+    /// Returns a local copy of the bundle at `bundle_url`, downloading it if it is not cached or has changed
+    pub async fn get(&self, bundle_url: &str) -> Result<CachedBundle<'_>> {
+        let url = Url::parse(bundle_url)?;
+        if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid file url {}", bundle_url))?;
+            return Ok(CachedBundle { path, pin: None });
+        }
+
+        let cached = self.lookup(bundle_url).await?;
+
+        let mut request = self.client.get(url).timeout(self.download_timeout);
+        if let Some(etag) = cached.as_ref().and_then(|entry| entry.etag.as_ref()) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(response) => response,
+            Err(e) => {
+                let Some(entry) = cached else {
+                    return Err(e).with_context(|| format!("failed to download {}", bundle_url));
+                };
+                warn!(
+                    "Unable to download {}, using cached bundle: {:?}",
+                    bundle_url, e
+                );
+                return self.touch(bundle_url, entry).await;
+            }
+        };
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = cached {
+                trace!("Cached bundle for {} is up to date", bundle_url);
+                return self.touch(bundle_url, entry).await;
+            }
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let bytes = response
+            .bytes()
+            .await
+            .with_context(|| format!("failed to download {}", bundle_url))?;
+        debug!("Downloaded {} ({} bytes)", bundle_url, bytes.len());
+
+        let sha256 = hex::encode(Sha256::digest(&bytes));
+        self.insert(
+            bundle_url,
+            CacheEntry {
+                etag,
+                sha256,
+                size: bytes.len() as u64,
+                last_used: Utc::now().timestamp_millis(),
+            },
+            &bytes,
+        )
+        .await
+    }
+
+    /// The cached entry for `bundle_url`, if its bundle is still on disk
+    async fn lookup(&self, bundle_url: &str) -> Result<Option<CacheEntry>> {
+        let _guard = self.index_lock.lock().await;
+        let index = self.load_index()?;
+        Ok(index
+            .entries
+            .get(bundle_url)
+            .filter(|entry| self.bundle_path(&entry.sha256).exists())
+            .cloned())
+    }
+
+    async fn touch(&self, bundle_url: &str, mut entry: CacheEntry) -> Result<CachedBundle<'_>> {
+        let _guard = self.index_lock.lock().await;
+        let mut index = self.load_index()?;
+        entry.last_used = Utc::now().timestamp_millis();
+        let bundle = self.pin(&entry.sha256);
+        index.entries.insert(bundle_url.to_string(), entry);
+        self.save_index(&index)?;
+        Ok(bundle)
+    }
+
+    async fn insert(
+        &self,
+        bundle_url: &str,
+        entry: CacheEntry,
+        bytes: &[u8],
+    ) -> Result<CachedBundle<'_>> {
+        let _guard = self.index_lock.lock().await;
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create bundle cache dir {}", self.dir.display()))?;
+
+        let path = self.bundle_path(&entry.sha256);
+        if !path.exists() {
+            let mut file = NamedTempFile::new_in(&self.dir)?;
+            file.write_all(bytes)?;
+            file.persist(&path)
+                .with_context(|| format!("failed to write bundle {}", path.display()))?;
+        }
+
+        // Pinned before evicting, so that the new bundle is not evicted straight away
+        let bundle = self.pin(&entry.sha256);
+        let mut index = self.load_index()?;
+        index.entries.insert(bundle_url.to_string(), entry);
+        self.evict(&mut index);
+        self.save_index(&index)?;
+        Ok(bundle)
+    }
+
+    /// Keeps the bundle with `sha256` from being evicted until the returned `CachedBundle` is dropped.
+    /// Called with the index locked, so that no eviction runs while the bundle is being pinned.
+    fn pin(&self, sha256: &str) -> CachedBundle<'_> {
+        *self
+            .pinned
+            .lock()
+            .expect("pinned bundles lock")
+            .entry(sha256.to_string())
+            .or_default() += 1;
+        CachedBundle {
+            path: self.bundle_path(sha256),
+            pin: Some((self, sha256.to_string())),
+        }
+    }
+
+    fn unpin(&self, sha256: &str) {
+        let mut pinned = self.pinned.lock().expect("pinned bundles lock");
+        if let Some(count) = pinned.get_mut(sha256) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(sha256);
+            }
+        }
+    }
+
+    /// Removes least recently used bundles, other than those in use, until the cache fits its size limit
+    fn evict(&self, index: &mut CacheIndex) {
+        let pinned = self.pinned.lock().expect("pinned bundles lock").clone();
+        loop {
+            let hashes: HashSet<(&String, u64)> = index
+                .entries
+                .values()
+                .map(|entry| (&entry.sha256, entry.size))
+                .collect();
+            let total_size: u64 = hashes.iter().map(|(_, size)| size).sum();
+            if total_size <= self.max_size {
+                return;
+            }
+
+            let Some(oldest_url) = index
+                .entries
+                .iter()
+                .filter(|(_, entry)| !pinned.contains_key(&entry.sha256))
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(url, _)| url.clone())
+            else {
+                return;
+            };
+            let Some(evicted) = index.entries.remove(&oldest_url) else {
+                return;
+            };
+            // Identical bundles published under several urls share one file
+            if index
+                .entries
+                .values()
+                .all(|entry| entry.sha256 != evicted.sha256)
+            {
+                let path = self.bundle_path(&evicted.sha256);
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!("Unable to evict bundle {}: {:?}", path.display(), e);
+                }
+            }
+            debug!("Evicted cached bundle for {}", oldest_url);
+        }
+    }
+
+    fn bundle_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(format!("{}.happ", sha256))
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join(INDEX_FILE_NAME)
+    }
+
+    fn load_index(&self) -> Result<CacheIndex> {
+        let path = self.index_path();
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!(
+                    "Unable to parse bundle cache index {}, starting empty: {:?}",
+                    path.display(),
+                    e
+                );
+                CacheIndex::default()
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CacheIndex::default()),
+            Err(e) => Err(e)
+                .with_context(|| format!("failed to read bundle cache index {}", path.display())),
+        }
+    }
+
+    fn save_index(&self, index: &CacheIndex) -> Result<()> {
+        let path = self.index_path();
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create bundle cache dir {}", dir.display()))?;
+        let mut file = NamedTempFile::new_in(dir)?;
+        file.write_all(serde_json::to_string_pretty(index)?.as_bytes())?;
+        file.persist(&path)
+            .with_context(|| format!("failed to write bundle cache index {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeHttpServer, FakeResponse};
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    /// Serves the bundle `/happ.happ` with the ETag of its current version, answering revalidation of that ETag with 304
+    async fn cdn(version: Arc<StdMutex<&'static str>>) -> FakeHttpServer {
+        FakeHttpServer::start(move |request| {
+            let version = *version.lock().unwrap();
+            let etag = format!("\"{}\"", version);
+            if request.header("If-None-Match") == Some(etag.as_str()) {
+                FakeResponse::new(304, "")
+            } else {
+                FakeResponse::new(200, version).with_header("ETag", &etag)
+            }
+        })
+        .await
+    }
+
+    /// Serves every path with its own path as the bundle, so that bundles at different urls differ
+    async fn path_cdn() -> FakeHttpServer {
+        FakeHttpServer::start(|request| FakeResponse::new(200, request.path.clone())).await
+    }
+
+    async fn get(cache: &BundleCache, url: &str) -> PathBuf {
+        cache.get(url).await.unwrap().path().to_path_buf()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn bundles_whose_etag_still_matches_are_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BundleCache::new(dir.path().to_path_buf(), 1024);
+        let server = cdn(Arc::new(StdMutex::new("v1"))).await;
+        let url = server.url("happ.happ").to_string();
+
+        let downloaded = get(&cache, &url).await;
+        let revalidated = get(&cache, &url).await;
+
+        assert_eq!(revalidated, downloaded);
+        assert_eq!(std::fs::read_to_string(&revalidated).unwrap(), "v1");
+        let requests = server.requests();
+        assert_eq!(requests[0].header("If-None-Match"), None);
+        assert_eq!(requests[1].header("If-None-Match"), Some("\"v1\""));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn bundles_whose_etag_changed_are_downloaded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BundleCache::new(dir.path().to_path_buf(), 1024);
+        let version = Arc::new(StdMutex::new("v1"));
+        let server = cdn(version.clone()).await;
+        let url = server.url("happ.happ").to_string();
+
+        let first = get(&cache, &url).await;
+        *version.lock().unwrap() = "v2";
+        let second = get(&cache, &url).await;
+
+        assert_ne!(second, first);
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "v2");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn cached_bundles_are_used_when_their_url_cannot_be_reached() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BundleCache::new(dir.path().to_path_buf(), 1024)
+            .with_download_timeout(Duration::from_secs(1));
+        let server = cdn(Arc::new(StdMutex::new("v1"))).await;
+        let cached_url = server.url("happ.happ").to_string();
+        let uncached_url = server.url("other.happ").to_string();
+
+        let downloaded = get(&cache, &cached_url).await;
+        drop(server);
+        // Lets the aborted server task close its listener
+        tokio::task::yield_now().await;
+
+        assert_eq!(get(&cache, &cached_url).await, downloaded);
+        assert!(cache.get(&uncached_url).await.is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn least_recently_used_bundles_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        // Room for two of the 7 byte bundles
+        let cache = BundleCache::new(dir.path().to_path_buf(), 14);
+        let server = path_cdn().await;
+
+        let a = get(&cache, server.url("a.happ").as_str()).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let b = get(&cache, server.url("b.happ").as_str()).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        get(&cache, server.url("a.happ").as_str()).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let c = get(&cache, server.url("c.happ").as_str()).await;
+
+        assert!(a.exists());
+        assert!(!b.exists());
+        assert!(c.exists());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn bundles_in_use_are_not_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        // Room for one of the 7 byte bundles
+        let cache = BundleCache::new(dir.path().to_path_buf(), 7);
+        let server = path_cdn().await;
+
+        let a = cache.get(server.url("a.happ").as_str()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let c = get(&cache, server.url("c.happ").as_str()).await;
+        assert!(a.path().exists());
+        assert!(c.exists());
+
+        let a_path = a.path().to_path_buf();
+        drop(a);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let b = get(&cache, server.url("b.happ").as_str()).await;
+        assert!(!a_path.exists());
+        assert!(!c.exists());
+        assert!(b.exists());
+    }
+}
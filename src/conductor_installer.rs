@@ -0,0 +1,178 @@
+use crate::backend::{AppInstall, ConductorAdmin, HostedInstaller, HostingRegistry};
+use crate::bundle_cache::BundleCache;
+use crate::integrity::BundleVerifier;
+use crate::mem_proof::{MembraneProofProvider, ReadOnlyMemProofs};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use holochain_types::dna::ActionHashB64;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{info, trace};
+use url::Url;
+
+/// The DNA properties that bind a servicelogger instance to the happ it logs for
+#[derive(Debug, Serialize)]
+struct ServiceloggerProperties {
+    bound_happ_id: String,
+}
+
+/// Installs hosted happs by taking the steps hpos-api's `/install_hosted_happ` takes,
+/// directly through the conductor and hha, so that it works without hpos-api and reports which step failed:
+/// 1. installs sl for the app,
+/// 2. holochain-enables the app's sl,
+/// 3. installs the app on holochain with the host agent pubkey,
+/// 4. holochain-enables the app,
+/// 5. holo-enables the app
+/// Steps that are already done (eg: an enabled sl) are skipped, and apps that are installed but disabled are only enabled.
+pub struct ConductorInstaller<C, R> {
+    conductor: Mutex<C>,
+    registry: Mutex<R>,
+    cache: Arc<BundleCache>,
+    mem_proofs: Arc<dyn MembraneProofProvider>,
+    verifier: BundleVerifier,
+    servicelogger_bundle_url: Url,
+}
+
+impl<C: ConductorAdmin, R: HostingRegistry> ConductorInstaller<C, R> {
+    pub fn new(
+        conductor: C,
+        registry: R,
+        cache: Arc<BundleCache>,
+        mem_proofs: Arc<dyn MembraneProofProvider>,
+        verifier: BundleVerifier,
+        servicelogger_bundle_url: Url,
+    ) -> Self {
+        ConductorInstaller {
+            conductor: Mutex::new(conductor),
+            registry: Mutex::new(registry),
+            cache,
+            mem_proofs,
+            verifier,
+            servicelogger_bundle_url,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: ConductorAdmin, R: HostingRegistry> HostedInstaller for ConductorInstaller<C, R> {
+    async fn install_hosted_happ(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+        dna_hashes: &[String],
+        special_installed_app_id: Option<&str>,
+    ) -> Result<()> {
+        // Held until the happ is installed, so that the cache does not evict the verified bundle in the meantime
+        let bundle = self
+            .verifier
+            .verify(happ_id, bundle_url, dna_hashes)
+            .await
+            .with_context(|| format!("refusing to install happ {}", happ_id))?;
+
+        let happ_id_hash = ActionHashB64::from_b64_str(happ_id)?;
+        let agent_key = self
+            .registry
+            .lock()
+            .await
+            .get_host_agent_pubkey()
+            .await
+            .context("failed to get the host agent pubkey")?;
+        let (enabled_app_ids, disabled_app_ids) = {
+            let mut conductor = self.conductor.lock().await;
+            (
+                conductor.list_enabled_app_ids().await?,
+                conductor.list_disabled_app_ids().await?,
+            )
+        };
+
+        let servicelogger_id = format!("{}::servicelogger", happ_id);
+        if enabled_app_ids.contains(&servicelogger_id) {
+            trace!("{} is already enabled", servicelogger_id);
+        } else if disabled_app_ids.contains(&servicelogger_id) {
+            self.conductor
+                .lock()
+                .await
+                .enable_app(&servicelogger_id)
+                .await
+                .with_context(|| step_failed(2, "enable servicelogger", happ_id))?;
+        } else {
+            let servicelogger_bundle_url = self.servicelogger_bundle_url.as_str();
+            let servicelogger_bundle = self.cache.get(servicelogger_bundle_url).await?;
+            let app = AppInstall {
+                installed_app_id: servicelogger_id.clone(),
+                bundle_path: servicelogger_bundle.path().to_path_buf(),
+                agent_key: agent_key.clone(),
+                membrane_proofs: ReadOnlyMemProofs::new(self.cache.clone())
+                    .get_membrane_proofs(&servicelogger_id, servicelogger_bundle_url)
+                    .await?,
+                properties: Some(serde_yaml::to_value(ServiceloggerProperties {
+                    bound_happ_id: happ_id.to_string(),
+                })?),
+            };
+            let mut conductor = self.conductor.lock().await;
+            conductor
+                .install_app(app)
+                .await
+                .with_context(|| step_failed(1, "install servicelogger", happ_id))?;
+            conductor
+                .enable_app(&servicelogger_id)
+                .await
+                .with_context(|| step_failed(2, "enable servicelogger", happ_id))?;
+        }
+
+        if let Some(special_installed_app_id) = special_installed_app_id {
+            trace!(
+                "Happ {} runs on core app {}, not installing an instance of it",
+                happ_id,
+                special_installed_app_id
+            );
+        } else if enabled_app_ids.iter().any(|id| id == happ_id) {
+            trace!("{} is already enabled", happ_id);
+        } else if disabled_app_ids.iter().any(|id| id == happ_id) {
+            self.conductor
+                .lock()
+                .await
+                .enable_app(happ_id)
+                .await
+                .with_context(|| step_failed(4, "enable happ", happ_id))?;
+        } else {
+            let app = AppInstall {
+                installed_app_id: happ_id.to_string(),
+                bundle_path: bundle.path().to_path_buf(),
+                agent_key,
+                membrane_proofs: self
+                    .mem_proofs
+                    .get_membrane_proofs(happ_id, bundle_url)
+                    .await
+                    .with_context(|| {
+                        format!("failed to get membrane proofs for happ {}", happ_id)
+                    })?,
+                properties: None,
+            };
+            let mut conductor = self.conductor.lock().await;
+            conductor
+                .install_app(app)
+                .await
+                .with_context(|| step_failed(3, "install happ", happ_id))?;
+            conductor
+                .enable_app(happ_id)
+                .await
+                .with_context(|| step_failed(4, "enable happ", happ_id))?;
+        }
+
+        self.registry
+            .lock()
+            .await
+            .holo_enable_happ(&happ_id_hash)
+            .await
+            .with_context(|| step_failed(5, "holo-enable happ", happ_id))?;
+
+        info!("Installed and enabled happ-id {}", happ_id);
+        Ok(())
+    }
+}
+
+fn step_failed(step: u8, name: &str, happ_id: &str) -> String {
+    format!("step {} ({}) failed for happ {}", step, name, happ_id)
+}
@@ -0,0 +1,229 @@
+use crate::backend::Connections;
+use crate::retry::is_connection_error;
+use crate::{reconcile, BundleCache, Installer, Settings, StateStore, StopSignal};
+use anyhow::{anyhow, Result};
+use hpos_hc_connect::holo_config::Config;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, trace, warn};
+
+/// The largest share of the interval that is randomly added to each wait,
+/// so that holoports started together do not all hit hha and hbs at the same moment
+const MAX_JITTER_FRACTION: f64 = 0.1;
+
+/// Reconciles the holoport every `interval` (plus jitter) until SIGTERM or SIGINT is received.
+/// A failed cycle is logged, and if a connection broke or timed out, connections are dropped
+/// to be reopened at the start of the next cycle.
+/// A signal received mid-cycle stops the cycle at the next action boundary, saving what it has done before shutting down.
+pub async fn run_daemon(config: &Config, settings: &Settings, interval: Duration) -> Result<()> {
+    info!(
+        "Starting holo-auto-installer daemon, reconciling every {:?}",
+        interval
+    );
+    let stop = StopSignal::default();
+    stop_on_signal(&stop)?;
+
+    let mut state = StateStore::load(&settings.state_dir)?;
+    let mut connections: Option<(Connections, Installer)> = None;
+    // Shared by every cycle, so that all installs take the same lock on the cache's index
+    let cache = Arc::new(BundleCache::from_settings(settings));
+
+    loop {
+        let result = async {
+            // Picks up what the `uninstall` subcommand saved since the last cycle
+            state.reload()?;
+            if connections.is_none() {
+                let open = async {
+                    let opened = Connections::open(config, settings).await?;
+                    // The installer calls hha through the same core app connection as the rest of the cycle
+                    let installer =
+                        Installer::open(config, settings, cache.clone(), &opened.core_app).await?;
+                    Ok::<_, anyhow::Error>((opened, installer))
+                };
+                tokio::select! {
+                    opened = open => connections = Some(opened?),
+                    _ = stop.stopped() => return Err(anyhow!("stopped while connecting")),
+                }
+            }
+            let (open, installer) = connections.as_mut().expect("connections were just opened");
+            reconcile(
+                &open.hbs,
+                &mut open.core_app,
+                &mut open.admin_websocket,
+                installer,
+                &cache,
+                &mut state,
+                settings,
+                &stop,
+            )
+            .await
+        }
+        .await;
+
+        match result {
+            Ok(report) => {
+                if report.has_failures() {
+                    error!(
+                        "Reconciliation cycle finished with {} failed actions",
+                        report.failed_actions().count()
+                    );
+                }
+                if report.has_connection_failures() {
+                    warn!("Reconnecting to hbs, the core app and the conductor on the next cycle");
+                    connections = None;
+                }
+            }
+            Err(e) if stop.is_stopped() => {
+                info!("Reconciliation cycle stopped: {:#}", e);
+            }
+            Err(e) => {
+                error!("Reconciliation cycle failed: {:?}", e);
+                if is_connection_error(&e) {
+                    warn!("Reconnecting to hbs, the core app and the conductor on the next cycle");
+                    connections = None;
+                }
+            }
+        }
+
+        if stop.is_stopped() {
+            break;
+        }
+
+        let delay = with_jitter(interval);
+        trace!("Next reconciliation cycle in {:?}", delay);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop.stopped() => break,
+        }
+    }
+
+    info!("holo-auto-installer daemon stopped");
+    Ok(())
+}
+
+/// Signals `stop` on the first SIGTERM or SIGINT. The handlers are registered before this returns,
+/// so from then on neither signal kills the process outright.
+fn stop_on_signal(stop: &StopSignal) -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let stop = stop.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM, shutting down once the current action finishes"),
+            _ = sigint.recv() => info!("Received SIGINT, shutting down once the current action finishes"),
+        }
+        stop.stop();
+    });
+    Ok(())
+}
+
+fn with_jitter(interval: Duration) -> Duration {
+    let mut bytes = [0u8; 8];
+    if let Err(e) = getrandom::getrandom(&mut bytes) {
+        warn!(
+            "Unable to get randomness for jitter, using plain interval: {:?}",
+            e
+        );
+        return interval;
+    }
+    let fraction = u64::from_le_bytes(bytes) as f64 / u64::MAX as f64;
+    interval + interval.mul_f64(MAX_JITTER_FRACTION * fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{AppInstall, ConductorAdmin};
+    use crate::test_support::{
+        fake_happ_id, fake_host_credentials, fake_host_preferences, no_pending_transactions,
+        FakeConductor, FakeHostBackend, FakeHostingRegistry, FakeInstaller,
+    };
+    use async_trait::async_trait;
+    use std::process::Command;
+
+    /// A conductor that receives SIGTERM while uninstalling an app, and only finishes once the run is told to stop
+    struct SigtermDuringUninstall {
+        conductor: FakeConductor,
+        stop: StopSignal,
+    }
+
+    #[async_trait(?Send)]
+    impl ConductorAdmin for SigtermDuringUninstall {
+        async fn list_enabled_app_ids(&mut self) -> Result<Vec<String>> {
+            self.conductor.list_enabled_app_ids().await
+        }
+
+        async fn list_disabled_app_ids(&mut self) -> Result<Vec<String>> {
+            self.conductor.list_disabled_app_ids().await
+        }
+
+        async fn disable_app(&mut self, installed_app_id: &str) -> Result<()> {
+            self.conductor.disable_app(installed_app_id).await
+        }
+
+        async fn uninstall_app(&mut self, installed_app_id: &str) -> Result<()> {
+            let status = Command::new("kill")
+                .args(["-TERM", &std::process::id().to_string()])
+                .status()?;
+            assert!(status.success(), "kill exited with {}", status);
+            tokio::time::timeout(Duration::from_secs(10), self.stop.stopped())
+                .await
+                .expect("SIGTERM stops the run");
+            self.conductor.uninstall_app(installed_app_id).await
+        }
+
+        async fn install_app(&mut self, app: AppInstall) -> Result<()> {
+            self.conductor.install_app(app).await
+        }
+
+        async fn enable_app(&mut self, installed_app_id: &str) -> Result<()> {
+            self.conductor.enable_app(installed_app_id).await
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn sigterm_stops_the_run_once_the_current_action_finishes() {
+        let stop = StopSignal::default();
+        stop_on_signal(&stop).expect("signal handlers are registered");
+
+        // Neither happ is published, so both instances are planned to be uninstalled
+        let first = format!("{}::agent", fake_happ_id(1));
+        let second = format!("{}::agent", fake_happ_id(2));
+        let conductor = FakeConductor::with_enabled_apps([first.clone(), second.clone()]);
+        let installer = FakeInstaller::new(&conductor);
+        let mut signalling = SigtermDuringUninstall {
+            conductor: conductor.clone(),
+            stop: stop.clone(),
+        };
+        let mut registry =
+            FakeHostingRegistry::new(fake_host_preferences(), no_pending_transactions());
+        let host = FakeHostBackend {
+            host_credentials: fake_host_credentials(),
+        };
+        let cache_dir = tempfile::tempdir().expect("temp dir");
+        let cache = BundleCache::new(cache_dir.path().to_path_buf(), 1024 * 1024);
+        let mut state = StateStore::in_memory();
+
+        let report = reconcile(
+            &host,
+            &mut registry,
+            &mut signalling,
+            &installer,
+            &cache,
+            &mut state,
+            &Settings::default(),
+            &stop,
+        )
+        .await
+        .expect("reconcile runs");
+
+        assert!(stop.is_stopped());
+        assert_eq!(report.actions.len(), 1);
+        assert_eq!(report.actions[0].action.installed_app_id, first);
+        assert!(!report.has_failures());
+        let conductor_state = conductor.state().clone();
+        assert_eq!(conductor_state.uninstalled, vec![first]);
+        assert_eq!(conductor_state.enabled, vec![second]);
+    }
+}
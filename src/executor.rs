@@ -0,0 +1,476 @@
+use crate::backend::{AppInstall, ConductorAdmin, HostedInstaller, HostingRegistry};
+use crate::bundle_cache::BundleCache;
+use crate::plan::{HappAction, PlannedAction, ReconciliationPlan};
+use crate::report::{ActionOutcome, ActionResult};
+use crate::settings::Settings;
+use crate::state::StateStore;
+use crate::timeout::Deadline;
+use anyhow::{anyhow, Result};
+use futures::future;
+use futures::stream::{self, StreamExt};
+use holochain_types::dna::ActionHashB64;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+pub const DEFAULT_MAX_CONCURRENT_INSTALLS: usize = 4;
+
+/// Tells a run to stop at the next action boundary, eg: when the daemon receives SIGTERM.
+/// Clones share the signal.
+#[derive(Debug, Clone)]
+pub struct StopSignal(Arc<watch::Sender<bool>>);
+
+impl Default for StopSignal {
+    fn default() -> Self {
+        StopSignal(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl StopSignal {
+    pub fn stop(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Waits until `stop` is called
+    pub async fn stopped(&self) {
+        let mut receiver = self.0.subscribe();
+        // The sender lives as long as `self`, so waiting cannot fail
+        let _ = receiver.wait_for(|stopped| *stopped).await;
+    }
+}
+
+/// Applies every action of the plan. Installs of listed apps are applied first, in order,
+/// then installs of hosted happs run, up to `Settings::max_concurrent_installs` at a time,
+/// then the remaining actions are applied in order. An install only counts as succeeded once the happ is enabled in the conductor.
+/// Actions still running when `deadline` passes are given up on, and those not yet started are not attempted;
+/// both are reported as timed out.
+/// Once `stop` is signalled, running actions are finished but no further action is started.
+/// A failed action is logged and reported, and does not stop the actions on other happs.
+/// Each applied action is recorded in `state`, and the outcome of every attempted action is returned.
+/// `cache` should be the cache `installer` uses, so that both take the same lock on its index.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_plan(
+    plan: &ReconciliationPlan,
+    conductor: &mut impl ConductorAdmin,
+    registry: &mut impl HostingRegistry,
+    installer: &impl HostedInstaller,
+    cache: &BundleCache,
+    state: &mut StateStore,
+    settings: &Settings,
+    deadline: Deadline,
+    stop: &StopSignal,
+) -> Vec<ActionOutcome> {
+    info!(
+        "Executing reconciliation plan with {} actions",
+        plan.actions.len()
+    );
+
+    let (listed_installs, rest): (Vec<&PlannedAction>, Vec<&PlannedAction>) =
+        plan.actions.iter().partition(|planned_action| {
+            matches!(planned_action.action, HappAction::InstallListed { .. })
+        });
+    let (installs, others): (Vec<&PlannedAction>, Vec<&PlannedAction>) = rest
+        .into_iter()
+        .partition(|planned_action| matches!(planned_action.action, HappAction::Install { .. }));
+
+    let mut outcomes = Vec::with_capacity(plan.actions.len());
+
+    apply_in_order(
+        plan,
+        &listed_installs,
+        conductor,
+        registry,
+        installer,
+        cache,
+        state,
+        deadline,
+        stop,
+        &mut outcomes,
+    )
+    .await;
+
+    let mut install_results: Vec<(usize, Duration, Result<()>)> =
+        stream::iter(installs.iter().enumerate())
+            .take_while(|_| future::ready(!stop.is_stopped()))
+            .map(|(index, planned_action)| async move {
+                let started = Instant::now();
+                let result = deadline.run(install(planned_action, installer)).await;
+                (index, started.elapsed(), result)
+            })
+            .buffer_unordered(settings.max_concurrent_installs.max(1))
+            .collect()
+            .await;
+    install_results.sort_by_key(|(index, _, _)| *index);
+
+    // An installer (eg: hpos-api) may report success without the app ending up enabled, so check the conductor ourselves
+    if install_results.iter().any(|(_, _, result)| result.is_ok()) {
+        match deadline.run(conductor.list_enabled_app_ids()).await {
+            Ok(enabled_app_ids) => {
+                for (index, _, result) in install_results.iter_mut() {
+                    if result.is_ok() {
+                        *result = verify_installed(installs[*index], &enabled_app_ids);
+                    }
+                }
+            }
+            Err(e) => {
+                for (_, _, result) in install_results.iter_mut() {
+                    if result.is_ok() {
+                        *result = Err(anyhow!("unable to verify install: {:#}", e));
+                    }
+                }
+            }
+        }
+    }
+
+    for (index, duration, result) in install_results {
+        record_outcome(
+            plan,
+            installs[index],
+            duration,
+            result,
+            state,
+            &mut outcomes,
+        );
+    }
+
+    apply_in_order(
+        plan,
+        &others,
+        conductor,
+        registry,
+        installer,
+        cache,
+        state,
+        deadline,
+        stop,
+        &mut outcomes,
+    )
+    .await;
+
+    if outcomes.len() < plan.actions.len() && stop.is_stopped() {
+        info!(
+            "Stopped early, leaving {} actions for the next run",
+            plan.actions.len() - outcomes.len()
+        );
+    }
+
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.is_failure())
+        .count();
+    let timed_out = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome.result, ActionResult::TimedOut { .. }))
+        .count();
+    info!(
+        "Done executing reconciliation plan: {} actions succeeded, {} failed ({} timed out)",
+        outcomes.len() - failed,
+        failed,
+        timed_out
+    );
+    outcomes
+}
+
+/// Applies `planned_actions` one after the other, until `stop` is signalled
+#[allow(clippy::too_many_arguments)]
+async fn apply_in_order(
+    plan: &ReconciliationPlan,
+    planned_actions: &[&PlannedAction],
+    conductor: &mut impl ConductorAdmin,
+    registry: &mut impl HostingRegistry,
+    installer: &impl HostedInstaller,
+    cache: &BundleCache,
+    state: &mut StateStore,
+    deadline: Deadline,
+    stop: &StopSignal,
+    outcomes: &mut Vec<ActionOutcome>,
+) {
+    for planned_action in planned_actions {
+        if stop.is_stopped() {
+            break;
+        }
+        let started = Instant::now();
+        let result = deadline
+            .run(apply_action(
+                planned_action,
+                conductor,
+                registry,
+                installer,
+                cache,
+            ))
+            .await;
+        let duration = started.elapsed();
+
+        record_outcome(plan, planned_action, duration, result, state, outcomes);
+    }
+}
+
+/// Records the outcome of an attempted action, and the action itself in `state` if it succeeded
+fn record_outcome(
+    plan: &ReconciliationPlan,
+    planned_action: &PlannedAction,
+    duration: Duration,
+    result: Result<()>,
+    state: &mut StateStore,
+    outcomes: &mut Vec<ActionOutcome>,
+) {
+    match result {
+        Ok(()) => {
+            let inputs = plan.inputs_for(planned_action).cloned().unwrap_or_default();
+            state.record(planned_action, inputs);
+            outcomes.push(ActionOutcome::new(
+                planned_action,
+                duration,
+                ActionResult::Succeeded,
+            ));
+        }
+        Err(e) => {
+            error!(
+                "Failed to {} {}: {:?}",
+                planned_action.action, planned_action.installed_app_id, e
+            );
+            outcomes.push(ActionOutcome::new(
+                planned_action,
+                duration,
+                ActionResult::from_error(&e),
+            ));
+        }
+    }
+}
+
+async fn apply_action(
+    planned_action: &PlannedAction,
+    conductor: &mut impl ConductorAdmin,
+    registry: &mut impl HostingRegistry,
+    installer: &impl HostedInstaller,
+    cache: &BundleCache,
+) -> Result<()> {
+    let PlannedAction {
+        installed_app_id,
+        action,
+        reason,
+    } = planned_action;
+
+    match action {
+        HappAction::Install { .. } => install(planned_action, installer).await?,
+        HappAction::InstallListed { bundle_url } => {
+            info!("Installing {} ({})", installed_app_id, reason);
+            // Held until the app is installed, so that the cache does not evict its bundle in the meantime
+            let bundle = cache.get(bundle_url).await?;
+            let app = AppInstall {
+                installed_app_id: installed_app_id.clone(),
+                bundle_path: bundle.path().to_path_buf(),
+                agent_key: registry.get_host_agent_pubkey().await?,
+                membrane_proofs: HashMap::new(),
+                properties: None,
+            };
+            conductor.install_app(app).await?;
+            conductor.enable_app(installed_app_id).await?;
+        }
+        HappAction::Enable => {
+            info!("Holochain-enabling {} ({})", installed_app_id, reason);
+            conductor.enable_app(installed_app_id).await?;
+        }
+        HappAction::Disable => {
+            info!("Holochain-disabling {} ({})", installed_app_id, reason);
+            conductor.disable_app(installed_app_id).await?;
+        }
+        HappAction::Uninstall => {
+            info!(
+                "Uninstalling {} from Holochain Conductor ({})",
+                installed_app_id, reason
+            );
+            conductor.uninstall_app(installed_app_id).await?;
+        }
+        HappAction::HoloDisable => {
+            info!("Holo-disabling {} ({})", installed_app_id, reason);
+            let happ_id_hash = ActionHashB64::from_b64_str(installed_app_id)?;
+            registry.holo_disable_happ(&happ_id_hash).await?;
+        }
+        HappAction::HoloEnable => {
+            info!("Holo-enabling {} ({})", installed_app_id, reason);
+            let happ_id_hash = ActionHashB64::from_b64_str(installed_app_id)?;
+            registry.holo_enable_happ(&happ_id_hash).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Fails if an app the install should have enabled is not enabled in the conductor
+fn verify_installed(planned_action: &PlannedAction, enabled_app_ids: &[String]) -> Result<()> {
+    let happ_id = &planned_action.installed_app_id;
+    let mut expected = vec![format!("{}::servicelogger", happ_id)];
+    if let HappAction::Install {
+        special_installed_app_id: None,
+        ..
+    } = &planned_action.action
+    {
+        expected.push(happ_id.clone());
+    }
+
+    let missing: Vec<String> = expected
+        .into_iter()
+        .filter(|installed_app_id| !enabled_app_ids.contains(installed_app_id))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "installer reported happ {} as installed, but {:?} are not enabled in the conductor",
+            happ_id,
+            missing
+        ));
+    }
+    Ok(())
+}
+
+async fn install(planned_action: &PlannedAction, installer: &impl HostedInstaller) -> Result<()> {
+    let PlannedAction {
+        installed_app_id,
+        action,
+        reason,
+    } = planned_action;
+    let HappAction::Install {
+        bundle_url,
+        dna_hashes,
+        special_installed_app_id,
+    } = action
+    else {
+        return Err(anyhow!("{} is not an install", action));
+    };
+
+    info!("Installing {} ({})", installed_app_id, reason);
+    installer
+        .install_hosted_happ(
+            installed_app_id,
+            bundle_url,
+            dna_hashes,
+            special_installed_app_id.as_deref(),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        fake_happ_id, fake_host_preferences, no_pending_transactions, FakeConductor,
+        FakeHostingRegistry, FakeInstaller,
+    };
+
+    fn hosted_install(n: u8) -> PlannedAction {
+        PlannedAction::new(
+            fake_happ_id(n).to_string(),
+            HappAction::Install {
+                bundle_url: format!("https://bundles.holo.host/{}.happ", n),
+                dna_hashes: vec![],
+                special_installed_app_id: None,
+            },
+            "eligible",
+        )
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn listed_installs_are_applied_before_hosted_installs_and_the_rest_after() {
+        let plan = ReconciliationPlan {
+            actions: vec![
+                PlannedAction::new(
+                    "listed",
+                    HappAction::InstallListed {
+                        bundle_url: "file:///bundles/listed.happ".to_string(),
+                    },
+                    "listed",
+                ),
+                hosted_install(1),
+                hosted_install(2),
+                PlannedAction::new("paused", HappAction::Disable, "paused"),
+                PlannedAction::new("unpublished", HappAction::Uninstall, "unpublished"),
+            ],
+            inputs: HashMap::new(),
+        };
+        let mut conductor = FakeConductor::with_enabled_apps(["paused", "unpublished"]);
+        let installer = FakeInstaller::new(&conductor);
+        let mut registry =
+            FakeHostingRegistry::new(fake_host_preferences(), no_pending_transactions());
+
+        let outcomes = execute_plan(
+            &plan,
+            &mut conductor,
+            &mut registry,
+            &installer,
+            &BundleCache::default(),
+            &mut StateStore::in_memory(),
+            &Settings::default(),
+            Deadline::after(Duration::from_secs(60)),
+            &StopSignal::default(),
+        )
+        .await;
+
+        assert!(outcomes.iter().all(|outcome| !outcome.is_failure()));
+        let changes = conductor.state().changes.clone();
+        assert_eq!(changes[..2], ["install_app listed", "enable_app listed"]);
+        let mut hosted_installs = changes[2..4].to_vec();
+        hosted_installs.sort();
+        assert_eq!(
+            hosted_installs,
+            [
+                format!("install_hosted_happ {}", fake_happ_id(1)),
+                format!("install_hosted_happ {}", fake_happ_id(2)),
+            ]
+        );
+        assert_eq!(
+            changes[4..],
+            ["disable_app paused", "uninstall_app unpublished"]
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn a_failed_action_does_not_stop_the_actions_after_it() {
+        let plan = ReconciliationPlan {
+            actions: vec![
+                // Not enabled, so the fake conductor fails to disable it
+                PlannedAction::new("missing", HappAction::Disable, "paused"),
+                PlannedAction::new("unpublished", HappAction::Uninstall, "unpublished"),
+            ],
+            inputs: HashMap::new(),
+        };
+        let mut conductor = FakeConductor::with_enabled_apps(["unpublished"]);
+        let installer = FakeInstaller::new(&conductor);
+        let mut registry =
+            FakeHostingRegistry::new(fake_host_preferences(), no_pending_transactions());
+        let mut state = StateStore::in_memory();
+
+        let outcomes = execute_plan(
+            &plan,
+            &mut conductor,
+            &mut registry,
+            &installer,
+            &BundleCache::default(),
+            &mut state,
+            &Settings::default(),
+            Deadline::after(Duration::from_secs(60)),
+            &StopSignal::default(),
+        )
+        .await;
+
+        let results: Vec<(&str, bool)> = outcomes
+            .iter()
+            .map(|outcome| {
+                (
+                    outcome.action.installed_app_id.as_str(),
+                    outcome.is_failure(),
+                )
+            })
+            .collect();
+        assert_eq!(results, vec![("missing", true), ("unpublished", false)]);
+        assert_eq!(conductor.state().uninstalled, vec!["unpublished"]);
+        assert_eq!(
+            state.state().apps.keys().collect::<Vec<_>>(),
+            vec!["unpublished"]
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use crate::plan::{Eligibility, HostingCheck, PlannedAction, ReconciliationSnapshot};
+use crate::planner::{hosting_checks, is_instance_of_happ, plan_reconciliation};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Pass,
+    Fail,
+    /// The check does not block hosting, but changes what the installer does
+    Info,
+}
+
+impl fmt::Display for CheckOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            CheckOutcome::Pass => "PASS",
+            CheckOutcome::Fail => "FAIL",
+            CheckOutcome::Info => "INFO",
+        };
+        f.write_str(label)
+    }
+}
+
+/// One condition the installer evaluates for a happ, with the concrete values it compared
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+    pub detail: String,
+}
+
+impl Check {
+    fn hosting(check: HostingCheck) -> Self {
+        Check::new(check.name, check.passed(), check.compared)
+    }
+
+    fn new(name: &'static str, passed: bool, detail: String) -> Self {
+        let outcome = if passed {
+            CheckOutcome::Pass
+        } else {
+            CheckOutcome::Fail
+        };
+        Check {
+            name,
+            outcome,
+            detail,
+        }
+    }
+
+    fn info(name: &'static str, detail: String) -> Self {
+        Check {
+            name,
+            outcome: CheckOutcome::Info,
+            detail,
+        }
+    }
+}
+
+/// Why a happ is or is not hosted on this holoport
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub happ_id: String,
+    pub is_published: bool,
+    /// The verdict of `check_eligibility`, which the planner decides on
+    pub eligibility: Eligibility,
+    /// Every hosting check of `check_eligibility`, followed by the install conditions
+    pub checks: Vec<Check>,
+    /// The actions a run would take on this happ and its instances
+    pub planned_actions: Vec<PlannedAction>,
+}
+
+/// Walks through every hosting check of `check_eligibility` and the install conditions the planner looks at for `happ_id`,
+/// so that it always agrees with what a run would do.
+pub fn explain_happ(snapshot: &ReconciliationSnapshot, happ_id: &str) -> Explanation {
+    let planned_actions = plan_reconciliation(snapshot)
+        .actions
+        .into_iter()
+        .filter(|action| is_instance_of_happ(happ_id, &action.installed_app_id))
+        .collect();
+
+    let Some(happ) = snapshot
+        .published_happs
+        .iter()
+        .find(|happ| happ.happ_id.to_string() == happ_id)
+    else {
+        return Explanation {
+            happ_id: happ_id.to_string(),
+            is_published: false,
+            eligibility: Eligibility::Eligible,
+            checks: vec![],
+            planned_actions,
+        };
+    };
+
+    let hosting_checks = hosting_checks(
+        happ_id,
+        &snapshot.suspended_happs,
+        &snapshot.host_credentials,
+        &snapshot.host_happ_preferences,
+        &snapshot.kyc_price_limits,
+        &snapshot.published_happ_details,
+    );
+    let eligibility = Eligibility::from(hosting_checks.as_slice());
+    let mut checks: Vec<Check> = hosting_checks.into_iter().map(Check::hosting).collect();
+
+    checks.push(Check::new(
+        "paused",
+        !happ.is_paused,
+        format!("happ paused by publisher in hha: {}", happ.is_paused),
+    ));
+
+    let servicelogger_id = format!("{}::servicelogger", happ_id);
+    let is_servicelogger_enabled = snapshot.enabled_happ_ids.contains(&servicelogger_id);
+    checks.push(Check::info(
+        "special app",
+        match &happ.special_installed_app_id {
+            Some(special_installed_app_id) => format!(
+                "happ runs on core app {}; {} enabled: {}",
+                special_installed_app_id, servicelogger_id, is_servicelogger_enabled
+            ),
+            None => "happ has no special installed app id".to_string(),
+        },
+    ));
+
+    checks.push(Check::info(
+        "already enabled",
+        format!(
+            "{} enabled in conductor: {}; enabled in host settings: {}",
+            happ_id,
+            snapshot.enabled_happ_ids.iter().any(|id| id == happ_id),
+            happ.host_settings.is_enabled
+        ),
+    ));
+
+    let is_removal_requested = snapshot
+        .removal_requested_app_ids
+        .iter()
+        .any(|id| is_instance_of_happ(happ_id, id));
+    checks.push(Check::info(
+        "removal requested",
+        format!(
+            "host asked to uninstall happ: {}; holo-enabled again in host settings: {}",
+            is_removal_requested, happ.host_settings.is_enabled
+        ),
+    ));
+
+    Explanation {
+        happ_id: happ_id.to_string(),
+        is_published: true,
+        eligibility,
+        checks,
+        planned_actions,
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_published {
+            writeln!(
+                f,
+                "Happ {} is not published for hosting in hha",
+                self.happ_id
+            )?;
+        } else {
+            writeln!(f, "Happ {}", self.happ_id)?;
+            for check in &self.checks {
+                writeln!(f, "  [{}] {}: {}", check.outcome, check.name, check.detail)?;
+            }
+        }
+
+        if self.planned_actions.is_empty() {
+            writeln!(f, "A run would not change this happ")?;
+        } else {
+            writeln!(f, "A run would:")?;
+            for action in &self.planned_actions {
+                writeln!(f, "  {}", action)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::suspension::{OverdueInvoice, SuspensionStage};
+    use crate::test_support::{fake_happ, fake_happ_id, fake_snapshot, fuel};
+    use crate::types::hbs::KycLevel;
+
+    fn outcomes(explanation: &Explanation) -> Vec<(&'static str, CheckOutcome)> {
+        explanation
+            .checks
+            .iter()
+            .map(|check| (check.name, check.outcome))
+            .collect()
+    }
+
+    fn lines(explanation: &Explanation) -> Vec<String> {
+        explanation
+            .to_string()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn every_hosting_check_is_listed_with_the_values_it_compared() {
+        let happ_id = fake_happ_id(1).to_string();
+        let mut happ = fake_happ(1);
+        happ.jurisdictions = vec!["DE".to_string()];
+        happ.categories = vec!["Games".to_string()];
+        happ.is_host_disabled = true;
+        let snapshot = fake_snapshot(vec![happ]);
+
+        let explanation = explain_happ(&snapshot, &happ_id);
+        assert_eq!(
+            outcomes(&explanation),
+            vec![
+                ("suspension", CheckOutcome::Pass),
+                ("publisher jurisdiction", CheckOutcome::Pass),
+                ("host jurisdiction", CheckOutcome::Fail),
+                ("category", CheckOutcome::Pass),
+                ("pricing", CheckOutcome::Pass),
+                ("kyc price limit", CheckOutcome::Pass),
+                ("invoice terms", CheckOutcome::Pass),
+                ("uptime", CheckOutcome::Pass),
+                ("host disabled", CheckOutcome::Fail),
+                ("kyc", CheckOutcome::Pass),
+                ("paused", CheckOutcome::Pass),
+                ("special app", CheckOutcome::Info),
+                ("already enabled", CheckOutcome::Info),
+                ("removal requested", CheckOutcome::Info),
+            ]
+        );
+        assert_eq!(explanation.eligibility.reasons().len(), 2);
+
+        let lines = lines(&explanation);
+        for expected in [
+            format!("Happ {}", happ_id),
+            "  [PASS] suspension: happ has no overdue hosting invoices".to_string(),
+            r#"  [PASS] publisher jurisdiction: publisher jurisdiction Some("DE") against host preference (none set)"#.to_string(),
+            r#"  [FAIL] host jurisdiction: host jurisdiction Some("DE") against happ excluded jurisdictions ["DE"]"#.to_string(),
+            r#"  [PASS] category: happ categories ["Games"] against host preference (none set)"#.to_string(),
+            "  [FAIL] host disabled: host disabled happ in hha: true".to_string(),
+            "  [PASS] kyc: host kyc level Level2 against required Level2".to_string(),
+            "  [PASS] paused: happ paused by publisher in hha: false".to_string(),
+            "A run would not change this happ".to_string(),
+        ] {
+            assert!(lines.contains(&expected), "{:?} not in {:#?}", expected, lines);
+        }
+    }
+
+    #[test]
+    fn failed_checks_are_followed_by_the_actions_a_run_would_take() {
+        let happ_id = fake_happ_id(1).to_string();
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.enabled_happ_ids = vec![happ_id.clone()];
+        snapshot.host_credentials.kyc = KycLevel::Level1;
+
+        let explanation = explain_happ(&snapshot, &happ_id);
+        assert!(!explanation.eligibility.is_eligible());
+
+        let lines = lines(&explanation);
+        assert!(lines
+            .contains(&"  [FAIL] kyc: host kyc level Level1 against required Level2".to_string()));
+        let actions_at = lines
+            .iter()
+            .position(|line| line == "A run would:")
+            .expect("a run changes the happ");
+        assert!(lines[actions_at + 1].starts_with(&format!("  disable      {} (", happ_id)));
+        assert!(lines[actions_at + 2].starts_with(&format!("  holo-disable {} (", happ_id)));
+        assert_eq!(lines.len(), actions_at + 3);
+    }
+
+    #[test]
+    fn happs_that_are_not_published_have_no_checks() {
+        let happ_id = fake_happ_id(2).to_string();
+        let snapshot = fake_snapshot(vec![fake_happ(1)]);
+
+        let explanation = explain_happ(&snapshot, &happ_id);
+        assert!(!explanation.is_published);
+        assert_eq!(
+            lines(&explanation),
+            vec![
+                format!("Happ {} is not published for hosting in hha", happ_id),
+                "A run would not change this happ".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn pricing_checks_show_the_happ_prices_against_the_host_prices_and_kyc_limit() {
+        let happ_id = fake_happ_id(1).to_string();
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        let unlimited = lines(&explain_happ(&snapshot, &happ_id));
+        assert!(unlimited.contains(
+            &"  [PASS] kyc price limit: no maximum price for a host of kyc Level2".to_string()
+        ));
+
+        snapshot.kyc_price_limits.level_2 = Some(fuel("2"));
+        let pricing = &mut snapshot
+            .published_happ_details
+            .get_mut(&happ_id)
+            .expect("happ is published")
+            .happ_pricing;
+        pricing.price_compute = fuel("0.5");
+        pricing.price_bandwidth = fuel("3");
+
+        let lines = lines(&explain_happ(&snapshot, &happ_id));
+        for expected in [
+            format!(
+                "  [FAIL] pricing: happ prices {}/{}/{} against host prices {}/{}/{} (compute/storage/bandwidth)",
+                fuel("0.5"),
+                fuel("1"),
+                fuel("3"),
+                fuel("1"),
+                fuel("1"),
+                fuel("1")
+            ),
+            format!(
+                "  [FAIL] kyc price limit: happ prices {}/{}/{} against maximum price {} for a host of kyc Level2 (compute/storage/bandwidth)",
+                fuel("0.5"),
+                fuel("1"),
+                fuel("3"),
+                fuel("2")
+            ),
+        ] {
+            assert!(lines.contains(&expected), "{:?} not in {:#?}", expected, lines);
+        }
+    }
+
+    #[test]
+    fn an_unknown_host_uptime_only_fails_happs_that_require_some() {
+        let happ_id = fake_happ_id(1).to_string();
+        let mut happ = fake_happ(1);
+        let mut snapshot = fake_snapshot(vec![happ.clone()]);
+        snapshot.host_credentials.uptime = None;
+        assert!(lines(&explain_happ(&snapshot, &happ_id)).contains(
+            &"  [PASS] uptime: host uptime unknown against happ minimum uptime 0".to_string()
+        ));
+
+        happ.min_uptime = 0.9;
+        let mut snapshot = fake_snapshot(vec![happ]);
+        snapshot.host_credentials.uptime = None;
+        let explanation = explain_happ(&snapshot, &happ_id);
+        assert!(!explanation.eligibility.is_eligible());
+        assert!(lines(&explanation).contains(
+            &"  [FAIL] uptime: host uptime unknown against happ minimum uptime 0.9".to_string()
+        ));
+    }
+
+    #[test]
+    fn overdue_invoices_show_whether_the_happ_is_still_within_the_grace_period() {
+        let happ_id = fake_happ_id(1).to_string();
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        for (stage, expected) in [
+            (
+                SuspensionStage::Warn,
+                "  [PASS] suspension: happ has hosting invoice invoice overdue for 2 days (warn stage, within the grace period)",
+            ),
+            (
+                SuspensionStage::Disable,
+                "  [FAIL] suspension: happ has hosting invoice invoice overdue for 2 days (disable stage)",
+            ),
+        ] {
+            snapshot.suspended_happs.insert(
+                happ_id.clone(),
+                OverdueInvoice {
+                    invoice: "invoice".to_string(),
+                    overdue_for: std::time::Duration::from_secs(2 * 24 * 60 * 60),
+                    stage,
+                },
+            );
+            let lines = lines(&explain_happ(&snapshot, &happ_id));
+            assert!(
+                lines.contains(&expected.to_string()),
+                "{:?} not in {:#?}",
+                expected,
+                lines
+            );
+        }
+    }
+
+    #[test]
+    fn happs_the_host_asked_to_uninstall_show_the_request_until_holo_enabled() {
+        let happ_id = fake_happ_id(1).to_string();
+        let mut happ = fake_happ(1);
+        happ.host_settings.is_enabled = false;
+        let mut snapshot = fake_snapshot(vec![happ]);
+        snapshot.disabled_happ_ids = vec![happ_id.clone()];
+        snapshot.removal_requested_app_ids = vec![happ_id.clone()];
+
+        let lines = lines(&explain_happ(&snapshot, &happ_id));
+        for expected in [
+            "  [INFO] removal requested: host asked to uninstall happ: true; holo-enabled again in host settings: false",
+            "A run would not change this happ",
+        ] {
+            assert!(
+                lines.contains(&expected.to_string()),
+                "{:?} not in {:#?}",
+                expected,
+                lines
+            );
+        }
+    }
+}
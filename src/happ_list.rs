@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::path::Path;
+use url::Url;
+
+/// A core or self-hosted happ listed in the happ-list file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ListedHapp {
+    pub app_id: String,
+    /// Also accepts a number, as in `version: 1`
+    #[serde(deserialize_with = "deserialize_version")]
+    pub version: String,
+    /// Bundle the happ is installed from. A happ listed without one is protected from removal, but never installed.
+    #[serde(default)]
+    pub dna_url: Option<Url>,
+    /// Not used by the installer, UIs are served by hpos
+    #[serde(default)]
+    pub ui_url: Option<Url>,
+}
+
+impl ListedHapp {
+    /// The `installed_app_id` of the listed version of the happ, eg: `core-app:0_2_1`
+    pub fn installed_app_id(&self) -> String {
+        format!("{}:{}", self.app_id, self.version)
+    }
+
+    /// Whether `installed_app_id` is the listed version of the happ, possibly with a `::<suffix>` (eg: a network seed)
+    pub fn is_listed_version(&self, installed_app_id: &str) -> bool {
+        let listed_id = self.installed_app_id();
+        installed_app_id == listed_id || installed_app_id.starts_with(&format!("{}::", listed_id))
+    }
+
+    /// Whether `installed_app_id` is any version of the happ
+    pub fn is_any_version(&self, installed_app_id: &str) -> bool {
+        installed_app_id == self.app_id
+            || installed_app_id.starts_with(&format!("{}:", self.app_id))
+    }
+}
+
+/// The happ-list file: the core happs a holoport runs on and the happs its host runs for themselves.
+/// Every listed happ is installed and kept up to date with the listed version, and is never removed by a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct HappList {
+    #[serde(default)]
+    pub core_happs: Vec<ListedHapp>,
+    #[serde(default)]
+    pub self_hosted_happs: Vec<ListedHapp>,
+}
+
+impl HappList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read happ list {}", path.display()))?;
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("failed to parse happ list {}", path.display()))
+    }
+
+    /// Every listed happ, core happs first
+    pub fn happs(&self) -> impl Iterator<Item = &ListedHapp> {
+        self.core_happs.iter().chain(self.self_hosted_happs.iter())
+    }
+
+    /// Whether `installed_app_id` is some version of a listed happ, which a run must never remove
+    pub fn is_listed(&self, installed_app_id: &str) -> bool {
+        self.happs()
+            .any(|happ| happ.is_any_version(installed_app_id))
+    }
+}
+
+fn deserialize_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::String(version) => Ok(version),
+        serde_yaml::Value::Number(version) => Ok(version.to_string()),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a version string or number, got {:?}",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,176 @@
+use crate::bundle_cache::{BundleCache, CachedBundle};
+use anyhow::{anyhow, Context, Result};
+use holochain_types::prelude::{AppManifest, DnaBundle, DnaHashB64, ResourceBytes};
+use mr_bundle::{Bundle, Location};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Reads pinned bundle hashes from a YAML map of happ id to the hex encoded sha256 of its bundle
+pub fn load_pinned_hashes(path: &Path) -> Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read pinned bundle hashes {}", path.display()))?;
+    serde_yaml::from_str(&contents)
+        .with_context(|| format!("failed to parse pinned bundle hashes {}", path.display()))
+}
+
+/// Checks a downloaded bundle against the hash pinned for its happ and the DNA hashes registered in hha,
+/// so that a bundle swapped on the CDN is never installed
+#[derive(Debug, Clone, Default)]
+pub struct BundleVerifier {
+    cache: Arc<BundleCache>,
+    /// Hex encoded sha256 of the expected bundle, keyed by happ id
+    pinned_hashes: HashMap<String, String>,
+}
+
+impl BundleVerifier {
+    pub fn new(cache: Arc<BundleCache>, pinned_hashes: HashMap<String, String>) -> Self {
+        BundleVerifier {
+            cache,
+            pinned_hashes,
+        }
+    }
+
+    /// Fails if the bundle at `bundle_url` does not match the hash pinned for `happ_id`,
+    /// or if its DNAs do not match the non-empty `registered_dna_hashes`.
+    /// Returns the local copy that was checked, which is what should be installed:
+    /// getting the bundle from the cache again may download different bytes.
+    pub async fn verify(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+        registered_dna_hashes: &[String],
+    ) -> Result<CachedBundle<'_>> {
+        let bundle = self.cache.get(bundle_url).await?;
+        let path = bundle.path();
+
+        let pinned_hash = self.pinned_hashes.get(happ_id);
+        if pinned_hash.is_none() && registered_dna_hashes.is_empty() {
+            warn!(
+                "No pinned or registered hashes for happ {}, installing its bundle unverified",
+                happ_id
+            );
+            return Ok(bundle);
+        }
+
+        if let Some(pinned_hash) = pinned_hash {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("failed to read bundle {}", path.display()))?;
+            let bundle_hash = hex::encode(Sha256::digest(&bytes));
+            if !bundle_hash.eq_ignore_ascii_case(pinned_hash) {
+                return Err(anyhow!(
+                    "bundle {} has sha256 {}, but {} is pinned for happ {}",
+                    bundle_url,
+                    bundle_hash,
+                    pinned_hash,
+                    happ_id
+                ));
+            }
+            debug!("Bundle of happ {} matches its pinned hash", happ_id);
+        }
+
+        if !registered_dna_hashes.is_empty() {
+            let dna_hashes = get_bundle_dna_hashes(path).await?;
+            let registered: BTreeSet<String> = registered_dna_hashes.iter().cloned().collect();
+            if dna_hashes != registered {
+                return Err(anyhow!(
+                    "bundle {} has DNA hashes {:?}, but happ {} is registered in hha with {:?}",
+                    bundle_url,
+                    dna_hashes,
+                    happ_id,
+                    registered
+                ));
+            }
+            debug!("DNAs of happ {} match those registered in hha", happ_id);
+        }
+
+        Ok(bundle)
+    }
+}
+
+/// Computes the hash of every DNA bundled in the happ bundle at `path`, with the network seed, properties
+/// and other modifiers its role sets in the manifest applied, as the conductor does when installing it
+async fn get_bundle_dna_hashes(path: &Path) -> Result<BTreeSet<String>> {
+    let bundle: Bundle<AppManifest> = Bundle::read_from_file(path).await?;
+    let AppManifest::V1(manifest) = bundle.manifest();
+
+    let mut dna_hashes = BTreeSet::new();
+    for role in &manifest.roles {
+        let Some(Location::Bundled(dna_path)) = &role.dna.location else {
+            return Err(anyhow!(
+                "DNA of role {} is not bundled in {}",
+                role.name,
+                path.display()
+            ));
+        };
+        let bytes: &ResourceBytes = bundle
+            .bundled_resources()
+            .get(dna_path)
+            .ok_or_else(|| anyhow!("bundle is missing DNA {}", dna_path.display()))?;
+        let modifiers = role.dna.modifiers.clone().serialized().with_context(|| {
+            format!(
+                "invalid DNA properties for role {} in {}",
+                role.name,
+                path.display()
+            )
+        })?;
+        let (_, dna_hash) = DnaBundle::decode(bytes)?.into_dna_file(modifiers).await?;
+        dna_hashes.insert(DnaHashB64::from(dna_hash).to_string());
+    }
+    Ok(dna_hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fake_dna_bytes, fake_dna_modifiers, write_fake_bundle};
+    use url::Url;
+
+    const HAPP_ID: &str = "uhCkkHappId";
+    const NETWORK_SEED: &str = "a network seed";
+
+    async fn dna_hash(network_seed: Option<&str>) -> String {
+        let (_, dna_hash) = DnaBundle::decode(&fake_dna_bytes())
+            .unwrap()
+            .into_dna_file(fake_dna_modifiers(network_seed).serialized().unwrap())
+            .await
+            .unwrap();
+        DnaHashB64::from(dna_hash).to_string()
+    }
+
+    #[tokio::test]
+    async fn dna_hashes_are_computed_with_the_network_seed_of_the_role() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_fake_bundle(dir.path(), Some(NETWORK_SEED)).await;
+
+        let seeded_hash = dna_hash(Some(NETWORK_SEED)).await;
+        assert_ne!(seeded_hash, dna_hash(None).await);
+        assert_eq!(
+            get_bundle_dna_hashes(&path).await.unwrap(),
+            BTreeSet::from([seeded_hash])
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_returns_the_checked_bundle_only_if_its_seeded_dnas_are_registered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_fake_bundle(dir.path(), Some(NETWORK_SEED)).await;
+        let bundle_url = Url::from_file_path(&path).unwrap().to_string();
+        let verifier = BundleVerifier::default();
+
+        assert!(verifier
+            .verify(HAPP_ID, &bundle_url, &[dna_hash(None).await])
+            .await
+            .is_err());
+        assert_eq!(
+            verifier
+                .verify(HAPP_ID, &bundle_url, &[dna_hash(Some(NETWORK_SEED)).await])
+                .await
+                .unwrap()
+                .path(),
+            path
+        );
+    }
+}
@@ -1,78 +1,269 @@
 // TODO: https://github.com/tokio-rs/tracing/issues/843
 #![allow(clippy::unit_arg)]
+pub mod backend;
+pub mod bundle_cache;
+pub mod conductor_installer;
+mod daemon;
+pub mod executor;
+pub mod explain;
+pub mod happ_list;
+pub mod integrity;
+pub mod mem_proof;
+pub mod plan;
+pub mod planner;
+pub mod protection;
+pub mod report;
+pub mod retry;
+pub mod settings;
+pub mod state;
+pub mod suspension;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod timeout;
 pub mod types;
 mod utils;
 
+pub use crate::backend::{
+    ConductorAdmin, HostBackend, HostedInstaller, HostingRegistry, HposApiInstaller, Installer,
+};
+pub use crate::bundle_cache::BundleCache;
+pub use crate::conductor_installer::ConductorInstaller;
+pub use crate::daemon::run_daemon;
+pub use crate::executor::{execute_plan, StopSignal};
+pub use crate::explain::{explain_happ, Explanation};
+pub use crate::happ_list::HappList;
+pub use crate::integrity::BundleVerifier;
+pub use crate::mem_proof::{JoiningCodeClient, MembraneProofProvider, ReadOnlyMemProofs};
+pub use crate::plan::{
+    ActionTarget, Eligibility, HappAction, Ineligibility, PlannedAction, ReconciliationPlan,
+    ReconciliationSnapshot,
+};
+pub use crate::planner::{plan_happ_uninstall, plan_reconciliation};
+pub use crate::protection::{InstalledAppKind, ProtectionPolicy};
+pub use crate::report::RunReport;
+pub use crate::settings::Settings;
+pub use crate::state::{InstallerState, StateStore};
+pub use crate::suspension::SuspensionPolicy;
+pub use crate::timeout::TimeoutSettings;
 pub use crate::types::happ::HappPreferences;
 pub use hpos_hc_connect::AdminWebsocket;
 
-use anyhow::Result;
-use holochain_types::dna::{hash_type::Agent, HoloHash};
-use hpos_hc_connect::{hha_agent::CoreAppAgent, holo_config::Config};
+use anyhow::{anyhow, Context, Result};
+use backend::Connections;
+use chrono::Utc;
+use holochain_types::prelude::AgentPubKey;
+use hpos_hc_connect::holo_config::Config;
+use itertools::Itertools;
+use report::ActionOutcome;
 use std::collections::HashMap;
-use tracing::{debug, error, info, trace};
-use types::hbs::{HbsClient, KycLevel};
-use types::PublishedHappDetails;
-use utils::{
-    get_all_published_hosted_happs, get_suspended_happs, handle_ineligible_happs,
-    install_holo_hosted_happs,
-};
+use std::sync::Arc;
+use suspension::SuspensionStage;
+use timeout::Deadline;
+use tracing::{debug, info, trace, warn};
+use types::{happ::PublishedHappPreferences, PublishedHappDetails};
+use utils::get_suspended_happs;
 
 /// 1. Gets all the holo-enabled happs from HHA
 /// 2. Suspends happs with overdue payments
 /// 3. Installs and enables (enables in holochain and holo) all new happs that were registered by a provider and holochain-disables those paused by provider in hha
-/// 4. Uninstalls happs that are ineligible for host (eg: holo-disabled, unallowed pricing for kyc level, incongruent price settings with publisher/happ)
-pub async fn run(config: &Config) -> Result<()> {
+/// 4. Enables (in holochain and holo) disabled happs that are eligible for hosting again
+/// 5. Uninstalls happs that are ineligible for host (eg: holo-disabled, unallowed pricing for kyc level, incongruent price settings with publisher/happ)
+/// Returns a report of every published happ's eligibility and of every action attempted.
+pub async fn run(config: &Config, settings: &Settings) -> Result<RunReport> {
     info!("Activating holo hosted apps");
-    let hbs_connect = HbsClient::connect()?;
-    let host_credentials = match hbs_connect.get_host_hosting_criteria().await {
-        Some(v) => v,
-        None => {
-            error!("Unable to get hosting criteria from HBS. Exiting...");
-            return Err(anyhow::anyhow!("Unable to get hosting criteria"));
-        }
+    let mut state = StateStore::load(&settings.state_dir)?;
+
+    let mut connections = Connections::open(config, settings).await?;
+    let cache = Arc::new(BundleCache::from_settings(settings));
+    let installer = Installer::open(config, settings, cache.clone(), &connections.core_app).await?;
+
+    reconcile(
+        &connections.hbs,
+        &mut connections.core_app,
+        &mut connections.admin_websocket,
+        &installer,
+        &cache,
+        &mut state,
+        settings,
+        &StopSignal::default(),
+    )
+    .await
+}
+
+/// Computes everything `run` would do to this holoport, without touching the conductor or hha
+pub async fn plan(config: &Config, settings: &Settings) -> Result<ReconciliationPlan> {
+    info!("Planning reconciliation of holo hosted apps");
+    let snapshot = fetch_snapshot(config, settings).await?;
+    Ok(plan_reconciliation(&snapshot))
+}
+
+/// Fetches the same inputs as `run` and explains why `happ_id` is or is not hosted on this holoport
+pub async fn explain(config: &Config, settings: &Settings, happ_id: &str) -> Result<Explanation> {
+    info!("Explaining hosting of {}", happ_id);
+    let snapshot = fetch_snapshot(config, settings).await?;
+    Ok(explain_happ(&snapshot, happ_id))
+}
+
+async fn fetch_snapshot(config: &Config, settings: &Settings) -> Result<ReconciliationSnapshot> {
+    let state = StateStore::load(&settings.state_dir)?;
+    let mut connections = Connections::open(config, settings).await?;
+    take_snapshot(
+        &connections.hbs,
+        &mut connections.core_app,
+        &mut connections.admin_websocket,
+        state.state(),
+        settings,
+    )
+    .await
+}
+
+/// Runs one full reconciliation against the given backends, recording what was done in `state`.
+/// `run` calls this with hbs, the core app, the conductor's admin websocket and hpos-api;
+/// the fakes in `test_support` let it be driven entirely in memory.
+/// Failed actions are reported in the returned `RunReport` rather than as an `Err`.
+/// Actions not done within the run timeout of the start of the run are reported as timed out.
+/// Once `stop` is signalled, the run stops at the next action boundary and saves what it has done.
+/// `cache` should be the bundle cache `installer` uses.
+#[allow(clippy::too_many_arguments)]
+pub async fn reconcile(
+    host: &impl HostBackend,
+    registry: &mut impl HostingRegistry,
+    conductor: &mut impl ConductorAdmin,
+    installer: &impl HostedInstaller,
+    cache: &BundleCache,
+    state: &mut StateStore,
+    settings: &Settings,
+    stop: &StopSignal,
+) -> Result<RunReport> {
+    let started_at = Utc::now();
+    let deadline = Deadline::after(settings.timeouts.run);
+
+    // Nothing has been changed while taking the snapshot, so it can be given up on right away
+    let snapshot = tokio::select! {
+        snapshot = deadline.run(take_snapshot(
+            host,
+            registry,
+            conductor,
+            state.state(),
+            settings,
+        )) => snapshot?,
+        _ = stop.stopped() => return Err(anyhow!("run stopped before any action was taken")),
     };
-    debug!("Got host credentials from hbs {:?}", host_credentials);
+    let plan = plan_reconciliation(&snapshot);
+    debug!("Reconciliation plan: {:?}", plan);
+
+    let outcomes = execute_plan(
+        &plan, conductor, registry, installer, cache, state, settings, deadline, stop,
+    )
+    .await;
+    state.save()?;
+
+    Ok(RunReport::new(started_at, &snapshot, outcomes))
+}
+
+/// Uninstalls every instance of `happ_id` from the conductor and holo-disables it for this holoport,
+/// recording what was done in the state like a run would. Returns the outcome of every action attempted.
+pub async fn uninstall(
+    config: &Config,
+    settings: &Settings,
+    happ_id: &str,
+) -> Result<Vec<ActionOutcome>> {
+    info!("Uninstalling {}", happ_id);
+    let mut state = StateStore::load(&settings.state_dir)?;
+    let mut connections = Connections::open(config, settings).await?;
+
+    let snapshot = take_snapshot(
+        &connections.hbs,
+        &mut connections.core_app,
+        &mut connections.admin_websocket,
+        state.state(),
+        settings,
+    )
+    .await?;
+    let plan = plan_happ_uninstall(&snapshot, happ_id);
+    debug!("Uninstall plan: {:?}", plan);
 
-    let mut core_app = CoreAppAgent::spawn(Some(config)).await?;
-    trace!("Connected to core app interface.");
+    let cache = Arc::new(BundleCache::from_settings(settings));
+    // The plan has no installs, so the installer is never called
+    let installer =
+        HposApiInstaller::from_settings(settings, cache.clone(), &connections.core_app)?;
+    let outcomes = execute_plan(
+        &plan,
+        &mut connections.admin_websocket,
+        &mut connections.core_app,
+        &installer,
+        &cache,
+        &mut state,
+        settings,
+        Deadline::after(settings.timeouts.run),
+        &StopSignal::default(),
+    )
+    .await;
+    state.save()?;
+    Ok(outcomes)
+}
+
+/// Reads what the installer has done to each app so far
+pub fn status(settings: &Settings) -> Result<InstallerState> {
+    Ok(StateStore::load(&settings.state_dir)?.state().clone())
+}
+
+/// Reads the current hbs, hha and conductor state that a reconciliation plan is computed from,
+/// together with the happ list, protection and suspension policies and kyc price limits from `settings`,
+/// and the apps a previous run disabled for a suspension, or removed on the host's request, from `state`
+pub async fn take_snapshot(
+    host: &impl HostBackend,
+    registry: &mut impl HostingRegistry,
+    conductor: &mut impl ConductorAdmin,
+    state: &InstallerState,
+    settings: &Settings,
+) -> Result<ReconciliationSnapshot> {
+    let mut host_credentials = host.get_host_credentials().await?;
+    debug!("Got host credentials from hbs {:?}", host_credentials);
+    host_credentials.uptime = settings
+        .uptime_source
+        .read_host_uptime(host_credentials.uptime)?;
+    trace!("Got host uptime : {:?}", host_credentials.uptime);
 
     // Suspend happs that have overdue payments
-    let pending_transactions = core_app.get_pending_transactions().await?;
+    let pending_transactions = registry.get_pending_transactions().await?;
     trace!("Got pending_transactions : {:?}", pending_transactions);
 
-    let suspended_happs = get_suspended_happs(pending_transactions);
+    let suspended_happs = get_suspended_happs(pending_transactions, &settings.suspension);
     trace!("Got suspended_happs : {:#?}", suspended_happs);
+    for (happ_id, overdue) in suspended_happs.iter() {
+        if overdue.stage == SuspensionStage::Warn {
+            warn!(
+                "Happ {} has hosting invoice {} overdue for {} days, it will be disabled once the grace period of {} days is over",
+                happ_id,
+                overdue.invoice,
+                overdue.overdue_days(),
+                settings.suspension.grace_days()
+            );
+        }
+    }
 
-    let published_happs = get_all_published_hosted_happs(&mut core_app).await?;
+    let published_happs = registry.get_published_happs().await?;
     trace!("Got published_happs : {:#?}", published_happs);
 
     // Get happ jurisdictions AND publisher jurisdiction for each happ
     let mut published_happ_details: HashMap<String, PublishedHappDetails> = HashMap::new();
-    let mut publisher_jurisdictions: HashMap<HoloHash<Agent>, Option<String>> = HashMap::new();
+    let mut publisher_jurisdictions: HashMap<AgentPubKey, Option<String>> = HashMap::new();
 
     for happ in published_happs.iter() {
-        let happ_prefs = core_app.get_happ_preferences(happ.happ_id.clone()).await?;
-        let publisher_pubkey = happ_prefs.provider_pubkey;
-
-        // If already have publisher pubkey stored in `publisher_jurisdictions` map, then grab the jurisdiction value and set value in `published_happ_details` map
-        // otherwise, make a call to hha to fetch the publisher jurisdiction and set in both the `published_happ_details` map and `publisher_jurisdictions` map
-        match publisher_jurisdictions.get(&publisher_pubkey) {
-            Some(jurisdiction) => {
-                published_happ_details.insert(
-                    happ.happ_id.clone().to_string(),
-                    PublishedHappDetails {
-                        publisher_jurisdiction: (*jurisdiction).clone(),
-                        happ_jurisdictions: happ.jurisdictions.clone(),
-                        should_exclude_happ_jurisdictions: happ.exclude_jurisdictions,
-                        happ_categories: happ.categories.clone(),
-                        is_disabled_by_host: happ.is_host_disabled,
-                    },
-                );
-            }
+        let PublishedHappPreferences {
+            publisher: publisher_pubkey,
+            pricing: happ_pricing,
+        } = registry.get_happ_preferences(&happ.happ_id).await?;
+        trace!("Got pricing for happ {} : {:?}", happ.happ_id, happ_pricing);
+
+        // If already have publisher pubkey stored in `publisher_jurisdictions` map, then reuse the jurisdiction value,
+        // otherwise, make a call to hha to fetch the publisher jurisdiction and store it in the `publisher_jurisdictions` map
+        let publisher_jurisdiction = match publisher_jurisdictions.get(&publisher_pubkey) {
+            Some(jurisdiction) => jurisdiction.clone(),
             None => {
-                let jurisdiction = core_app
-                    .get_publisher_jurisdiction(publisher_pubkey.clone())
+                let jurisdiction = registry
+                    .get_publisher_jurisdiction(&publisher_pubkey)
                     .await?;
                 trace!(
                     "Got jurisdiction for publisher {:?} : {:?}",
@@ -80,34 +271,56 @@ pub async fn run(config: &Config) -> Result<()> {
                     jurisdiction
                 );
                 publisher_jurisdictions.insert(publisher_pubkey, jurisdiction.clone());
-                published_happ_details.insert(
-                    happ.happ_id.clone().to_string(),
-                    PublishedHappDetails {
-                        publisher_jurisdiction: jurisdiction,
-                        happ_jurisdictions: happ.jurisdictions.clone(),
-                        should_exclude_happ_jurisdictions: happ.exclude_jurisdictions,
-                        happ_categories: happ.categories.clone(),
-                        is_disabled_by_host: happ.is_host_disabled,
-                    },
-                );
+                jurisdiction
             }
-        }
+        };
+
+        published_happ_details.insert(
+            happ.happ_id.to_string(),
+            PublishedHappDetails {
+                publisher_jurisdiction,
+                happ_jurisdictions: happ.jurisdictions.clone(),
+                should_exclude_happ_jurisdictions: happ.exclude_jurisdictions,
+                happ_categories: happ.categories.clone(),
+                is_disabled_by_host: happ.is_host_disabled,
+                happ_pricing,
+                min_uptime: happ.min_uptime,
+            },
+        );
     }
 
-    let host_happ_preferences = core_app.get_host_preferences().await?.into();
+    let host_happ_preferences = registry.get_host_preferences().await?;
 
-    let is_host_kyc_level_2 = host_credentials.clone().kyc == KycLevel::Level2;
+    let enabled_happ_ids: Vec<String> = conductor
+        .list_enabled_app_ids()
+        .await
+        .context("failed to get installed hApps")?
+        .into_iter()
+        .unique()
+        .collect();
+    trace!("enabled_happs {:?}", enabled_happ_ids);
 
-    install_holo_hosted_happs(config.admin_port, &published_happs, is_host_kyc_level_2).await?;
+    let disabled_happ_ids: Vec<String> = conductor
+        .list_disabled_app_ids()
+        .await
+        .context("failed to get disabled hApps")?
+        .into_iter()
+        .unique()
+        .collect();
+    trace!("disabled_happs {:?}", disabled_happ_ids);
 
-    handle_ineligible_happs(
-        &mut core_app,
-        config.admin_port,
+    Ok(ReconciliationSnapshot {
+        published_happs,
         suspended_happs,
         host_credentials,
         host_happ_preferences,
+        kyc_price_limits: settings.kyc_price_limits.clone(),
         published_happ_details,
-    )
-    .await?;
-    Ok(())
+        enabled_happ_ids,
+        disabled_happ_ids,
+        happ_list: settings.happ_list.clone(),
+        protection: settings.protection.clone(),
+        suspension_disabled_app_ids: state.suspension_disabled_app_ids(),
+        removal_requested_app_ids: state.removal_requested_app_ids(),
+    })
 }
@@ -1,20 +1,369 @@
 // TODO: https://github.com/tokio-rs/tracing/issues/843
 #![allow(clippy::unit_arg)]
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use holo_auto_installer::{
+    bundle_cache::DEFAULT_BUNDLE_CACHE_DIR,
+    integrity::load_pinned_hashes,
+    protection::AppIdPattern,
+    retry::RetrySettings,
+    settings::{HposApiEndpoint, InstallBackend, UptimeSource, DEFAULT_HPOS_API_URL},
+    state::DEFAULT_STATE_DIR,
+    types::happ::KycPriceLimits,
+    HappList, ProtectionPolicy, RunReport, Settings, SuspensionPolicy, TimeoutSettings,
+};
+use holofuel_types::fuel::Fuel;
 use hpos_hc_connect::holo_config::Config;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use structopt::StructOpt;
 use tracing::instrument;
 use tracing_subscriber::EnvFilter;
+use url::Url;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "holo-auto-installer")]
+struct Opt {
+    /// hpos config file the host's email and agent are read from
+    #[structopt(long, env = "HPOS_CONFIG_PATH", parse(from_os_str))]
+    config_path: Option<PathBuf>,
+    /// Base url of hbs
+    #[structopt(long, env = "HBS_URL")]
+    hbs_url: Option<Url>,
+    /// Format of log lines: `text` or `json`
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+    /// Directory in which the installer records what it did to each app
+    #[structopt(long, env = "STATE_DIR", default_value = DEFAULT_STATE_DIR, parse(from_os_str))]
+    state_dir: PathBuf,
+    /// Joining-code service to request membrane proofs from (hosted happs are installed with read-only proofs if unset)
+    #[structopt(long, env = "JOINING_CODE_URL")]
+    joining_code_url: Option<Url>,
+    /// Directory in which downloaded happ bundles are cached
+    #[structopt(long, env = "BUNDLE_CACHE_DIR", default_value = DEFAULT_BUNDLE_CACHE_DIR, parse(from_os_str))]
+    bundle_cache_dir: PathBuf,
+    /// Size in MiB past which the least recently used cached bundles are evicted
+    #[structopt(long, default_value = "1024")]
+    bundle_cache_size: u64,
+    /// YAML file mapping happ ids to the sha256 their downloaded bundle must have
+    #[structopt(long, env = "PINNED_BUNDLE_HASHES", parse(from_os_str))]
+    pinned_bundle_hashes: Option<PathBuf>,
+    /// How many hosted happs are installed at the same time
+    #[structopt(long, default_value = "4")]
+    max_concurrent_installs: usize,
+    /// Base url of hpos-api
+    #[structopt(long, env = "HPOS_API_URL", default_value = DEFAULT_HPOS_API_URL)]
+    hpos_api_url: Url,
+    /// Unix socket hpos-api listens on, used instead of the host and port of `--hpos-api-url`
+    #[structopt(long, env = "HPOS_API_SOCKET", parse(from_os_str))]
+    hpos_api_socket: Option<PathBuf>,
+    /// Value of the `Authorization` header sent to hpos-api (eg: `Bearer <token>`)
+    #[structopt(long, env = "HPOS_API_AUTHORIZATION", hide_env_values = true)]
+    hpos_api_authorization: Option<String>,
+    /// What hosted happs are installed through: `hpos-api`, or `conductor` to take each install step directly
+    #[structopt(long, default_value = "hpos-api")]
+    install_backend: InstallBackend,
+    /// Servicelogger bundle installed alongside each hosted happ, required by `--install-backend conductor`
+    #[structopt(long, env = "SL_BUNDLE_URL")]
+    servicelogger_bundle_url: Option<Url>,
+    /// App id, or `*` pattern, of an app a run must never disable or uninstall (comma separated, or given several times)
+    #[structopt(long = "protect-app", env = "PROTECTED_APPS", use_delimiter = true)]
+    protected_apps: Vec<AppIdPattern>,
+    /// App id, or `*` pattern, of an app that is not protected even if it looks like a core app or a servicelogger
+    #[structopt(long = "unprotect-app", env = "UNPROTECTED_APPS", use_delimiter = true)]
+    unprotected_apps: Vec<AppIdPattern>,
+    /// Where the host's measured uptime is read from: `hbs`, or `file:<path>` for a file holding a fraction between 0 and 1
+    #[structopt(long, env = "UPTIME_SOURCE", default_value = "hbs")]
+    uptime_source: UptimeSource,
+    /// Days a hosting invoice may be overdue before its happ is disabled, during which the host is only warned
+    #[structopt(long, default_value = "0")]
+    suspension_grace_days: u64,
+    /// Days a hosting invoice may be overdue before its happ's identified instances are uninstalled and it is holo-disabled,
+    /// no fewer than `--suspension-grace-days`
+    #[structopt(long, default_value = "0")]
+    suspension_uninstall_days: u64,
+    /// Most a happ may pay a kyc level 1 host per unit of any resource (unlimited if unset)
+    #[structopt(long = "kyc-1-max-price", env = "KYC_1_MAX_PRICE")]
+    kyc_level_1_max_price: Option<Fuel>,
+    /// Most a happ may pay a kyc level 2 host per unit of any resource (unlimited if unset)
+    #[structopt(long = "kyc-2-max-price", env = "KYC_2_MAX_PRICE")]
+    kyc_level_2_max_price: Option<Fuel>,
+    /// How many times a failed call to the conductor, hha, hbs or hpos-api is retried (0 disables retrying)
+    #[structopt(long, default_value = "3")]
+    retries: usize,
+    /// Milliseconds before the first retry, doubled for every further retry
+    #[structopt(long, default_value = "500")]
+    retry_delay_ms: u64,
+    /// Longest wait between retries, in milliseconds
+    #[structopt(long, default_value = "30000")]
+    retry_max_delay_ms: u64,
+    /// Retry after exactly the backoff delay, without randomizing it
+    #[structopt(long)]
+    no_retry_jitter: bool,
+    /// Seconds each call to the conductor's admin interface may take
+    #[structopt(long, default_value = "30")]
+    conductor_timeout: u64,
+    /// Seconds each zome call to hha and holofuel may take
+    #[structopt(long, default_value = "60")]
+    hha_timeout: u64,
+    /// Seconds each request to hbs, hpos-api and the joining-code service may take
+    #[structopt(long, default_value = "60")]
+    http_timeout: u64,
+    /// Seconds each happ bundle download may take
+    #[structopt(long, default_value = "300")]
+    download_timeout: u64,
+    /// Seconds a whole reconciliation run may take, after which the remaining actions are reported as timed out
+    #[structopt(long, default_value = "1800")]
+    run_timeout: u64,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Reconcile the holoport with hha and hbs: install eligible hosted happs and remove ineligible ones
+    Run(RunOpt),
+    /// Print every install, enable, disable, uninstall, holo-enable and holo-disable a run would make, without making them
+    Plan {
+        #[structopt(flatten)]
+        holochain: Config,
+    },
+    /// Print every check that decides whether a happ is hosted on this holoport, and what a run would do to it
+    Explain {
+        /// The hha id of the happ
+        happ_id: String,
+        #[structopt(flatten)]
+        holochain: Config,
+    },
+    /// Print the last action the installer took on each app, when, and why
+    Status,
+    /// Uninstall every instance of a hosted happ from the conductor and holo-disable it for this holoport
+    Uninstall {
+        /// The hha id of the happ
+        happ_id: String,
+        #[structopt(flatten)]
+        holochain: Config,
+    },
+}
+
+impl Command {
+    /// How to reach holochain, for the commands that connect to it
+    fn holochain_config(&self) -> Option<&Config> {
+        match self {
+            Command::Run(RunOpt { holochain, .. })
+            | Command::Plan { holochain }
+            | Command::Explain { holochain, .. }
+            | Command::Uninstall { holochain, .. } => Some(holochain),
+            Command::Status => None,
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RunOpt {
+    /// Print every install, enable, disable, uninstall, holo-enable and holo-disable the run would make, without making them
+    #[structopt(long, conflicts_with = "daemon")]
+    dry_run: bool,
+    /// Keep running and reconcile the holoport every `--interval` seconds instead of once
+    #[structopt(long)]
+    daemon: bool,
+    /// Seconds between reconciliation cycles in daemon mode (a random jitter of up to 10% is added)
+    #[structopt(long, default_value = "300")]
+    interval: u64,
+    /// Emit a report of the run in the given format (only `json` is supported)
+    #[structopt(long, conflicts_with_all = &["dry-run", "daemon"])]
+    report: Option<ReportFormat>,
+    /// Write the report to this file instead of stdout
+    #[structopt(long, requires = "report", parse(from_os_str))]
+    report_file: Option<PathBuf>,
+    #[structopt(flatten)]
+    holochain: Config,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("Unsupported log format {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ReportFormat {
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(anyhow!("Unsupported report format {}", s)),
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let opt = Opt::from_args();
     let filter = EnvFilter::from_default_env().add_directive("again=trace".parse().unwrap());
-    tracing_subscriber::fmt().with_env_filter(filter).init();
-    spawn().await
+    match opt.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(filter)
+            .init(),
+    }
+    spawn(opt).await
 }
 
 #[instrument(err)]
-async fn spawn() -> Result<()> {
-    let config = Config::load();
+async fn spawn(opt: Opt) -> Result<()> {
+    let settings = Settings {
+        state_dir: opt.state_dir,
+        hbs_url: opt.hbs_url,
+        hpos_config_path: opt.config_path,
+        joining_code_url: opt.joining_code_url,
+        bundle_cache_dir: opt.bundle_cache_dir,
+        bundle_cache_size_mib: opt.bundle_cache_size,
+        pinned_bundle_hashes: match &opt.pinned_bundle_hashes {
+            Some(path) => load_pinned_hashes(path)?,
+            None => HashMap::new(),
+        },
+        max_concurrent_installs: opt.max_concurrent_installs,
+        hpos_api: HposApiEndpoint::new(
+            opt.hpos_api_url,
+            opt.hpos_api_socket,
+            opt.hpos_api_authorization,
+        ),
+        install_backend: opt.install_backend,
+        servicelogger_bundle_url: opt.servicelogger_bundle_url,
+        retry: RetrySettings {
+            max_retries: opt.retries,
+            base_delay: Duration::from_millis(opt.retry_delay_ms),
+            max_delay: Duration::from_millis(opt.retry_max_delay_ms),
+            jitter: !opt.no_retry_jitter,
+        },
+        timeouts: TimeoutSettings {
+            conductor: Duration::from_secs(opt.conductor_timeout),
+            hha: Duration::from_secs(opt.hha_timeout),
+            http: Duration::from_secs(opt.http_timeout),
+            download: Duration::from_secs(opt.download_timeout),
+            run: Duration::from_secs(opt.run_timeout),
+        },
+        protection: ProtectionPolicy {
+            allow: opt.protected_apps,
+            deny: opt.unprotected_apps,
+        },
+        uptime_source: opt.uptime_source,
+        suspension: SuspensionPolicy::from_days(
+            opt.suspension_grace_days,
+            opt.suspension_uninstall_days,
+        )?,
+        kyc_price_limits: KycPriceLimits {
+            level_1: opt.kyc_level_1_max_price,
+            level_2: opt.kyc_level_2_max_price,
+        },
+        happ_list: match opt.command.holochain_config() {
+            Some(config) => HappList::load(&config.happ_list_path)?,
+            None => HappList::default(),
+        },
+    };
+
+    match opt.command {
+        Command::Status => {
+            print!("{}", holo_auto_installer::status(&settings)?);
+            Ok(())
+        }
+        Command::Plan { holochain } => {
+            print!(
+                "{}",
+                holo_auto_installer::plan(&holochain, &settings).await?
+            );
+            Ok(())
+        }
+        Command::Explain { happ_id, holochain } => {
+            print!(
+                "{}",
+                holo_auto_installer::explain(&holochain, &settings, &happ_id).await?
+            );
+            Ok(())
+        }
+        Command::Uninstall { happ_id, holochain } => {
+            let outcomes = holo_auto_installer::uninstall(&holochain, &settings, &happ_id).await?;
+            let failed = outcomes
+                .iter()
+                .filter(|outcome| outcome.is_failure())
+                .count();
+            if failed > 0 {
+                return Err(anyhow!(
+                    "{} of {} actions to uninstall {} failed",
+                    failed,
+                    outcomes.len(),
+                    happ_id
+                ));
+            }
+            for outcome in outcomes {
+                println!("{}", outcome.action);
+            }
+            Ok(())
+        }
+        Command::Run(run_opt) => run(run_opt, &settings).await,
+    }
+}
+
+async fn run(opt: RunOpt, settings: &Settings) -> Result<()> {
+    let config = opt.holochain;
+
+    if opt.dry_run {
+        let plan = holo_auto_installer::plan(&config, settings).await?;
+        print!("{}", plan);
+        return Ok(());
+    }
+
+    if opt.daemon {
+        return holo_auto_installer::run_daemon(
+            &config,
+            settings,
+            Duration::from_secs(opt.interval),
+        )
+        .await;
+    }
+
+    let report = holo_auto_installer::run(&config, settings).await?;
+
+    if let Some(ReportFormat::Json) = opt.report {
+        write_report(&report, opt.report_file)?;
+    }
+
+    if report.has_failures() {
+        return Err(anyhow!(
+            "{} of {} actions failed ({} timed out)",
+            report.failed_actions().count(),
+            report.actions.len(),
+            report.timed_out_actions().count()
+        ));
+    }
+    Ok(())
+}
 
-    holo_auto_installer::run(&config).await
+fn write_report(report: &RunReport, report_file: Option<PathBuf>) -> Result<()> {
+    let json = serde_json::to_string_pretty(report)?;
+    match report_file {
+        Some(path) => std::fs::write(path, json)?,
+        None => println!("{}", json),
+    }
+    Ok(())
 }
@@ -0,0 +1,297 @@
+use crate::backend::HostingRegistry;
+use crate::bundle_cache::BundleCache;
+use crate::retry::RetrySettings;
+use crate::timeout::TimeoutSettings;
+use crate::utils::get_bundle_role_names;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::prelude::*;
+use holochain_types::prelude::{
+    holochain_serial, MembraneProof, SerializedBytes, Timestamp, UnsafeBytes,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, trace};
+use url::Url;
+
+/// Source of the membrane proofs a hosted happ is installed with
+#[async_trait(?Send)]
+pub trait MembraneProofProvider {
+    /// Returns a membrane proof for every role in the bundle at `bundle_url`.
+    /// Fails rather than returning a partial set, so that a happ is never installed with wrong proofs.
+    async fn get_membrane_proofs(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+    ) -> Result<HashMap<String, MembraneProof>>;
+}
+
+/// Gives every role the read-only membrane proof, which lets the host agent join as a read-only instance
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnlyMemProofs {
+    cache: Arc<BundleCache>,
+}
+
+impl ReadOnlyMemProofs {
+    pub fn new(cache: Arc<BundleCache>) -> Self {
+        ReadOnlyMemProofs { cache }
+    }
+}
+
+#[async_trait(?Send)]
+impl MembraneProofProvider for ReadOnlyMemProofs {
+    async fn get_membrane_proofs(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+    ) -> Result<HashMap<String, MembraneProof>> {
+        let roles = get_bundle_role_names(&self.cache, bundle_url)
+            .await
+            .with_context(|| format!("failed to read roles of happ {}", happ_id))?;
+
+        Ok(roles
+            .into_iter()
+            .map(|role| (role, membrane_proof(vec![0]))) // The read only memproof is [0] (or in base64 `AA==`)
+            .collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, SerializedBytes)]
+struct JoiningCodeRequest {
+    happ_id: String,
+    roles: Vec<String>,
+    agent_pub_key: String,
+    timestamp: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct JoiningCodeResponse {
+    /// Base64 encoded membrane proof, keyed by role name
+    membrane_proofs: HashMap<String, String>,
+}
+
+/// Requests the host agent's membrane proofs from a joining-code service.
+/// The request is signed with the host agent key and sent in the `X-Signature` header, as for hbs.
+/// The key is reached through `registry`, which should be the run's core app connection.
+pub struct JoiningCodeClient<R> {
+    endpoint: Url,
+    client: reqwest::Client,
+    cache: Arc<BundleCache>,
+    registry: Mutex<R>,
+    /// How long the request for membrane proofs may take
+    timeout: Duration,
+    retry: RetrySettings,
+}
+
+impl<R: HostingRegistry> JoiningCodeClient<R> {
+    pub fn new(endpoint: Url, cache: Arc<BundleCache>, registry: R) -> Self {
+        JoiningCodeClient {
+            endpoint,
+            client: reqwest::Client::new(),
+            cache,
+            registry: Mutex::new(registry),
+            timeout: TimeoutSettings::default().http,
+            retry: RetrySettings::default(),
+        }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn with_retry(self, retry: RetrySettings) -> Self {
+        Self { retry, ..self }
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: HostingRegistry> MembraneProofProvider for JoiningCodeClient<R> {
+    async fn get_membrane_proofs(
+        &self,
+        happ_id: &str,
+        bundle_url: &str,
+    ) -> Result<HashMap<String, MembraneProof>> {
+        let roles = get_bundle_role_names(&self.cache, bundle_url)
+            .await
+            .with_context(|| format!("failed to read roles of happ {}", happ_id))?;
+
+        let mut registry = self.registry.lock().await;
+        let agent_pub_key = registry
+            .get_host_agent_pubkey()
+            .await
+            .context("failed to get the host agent pubkey")?;
+
+        let payload = JoiningCodeRequest {
+            happ_id: happ_id.to_string(),
+            roles: roles.clone(),
+            agent_pub_key: agent_pub_key.to_string(),
+            timestamp: Timestamp::now().as_millis(),
+        };
+        let payload_bytes = SerializedBytes::try_from(payload.clone())?
+            .bytes()
+            .to_owned();
+        let signature = registry
+            .sign_raw(payload_bytes)
+            .await
+            .context("failed to sign the membrane proof request")?;
+        drop(registry);
+
+        debug!(
+            "Requesting membrane proofs for happ {} from {}",
+            happ_id, self.endpoint
+        );
+        let signature = BASE64_STANDARD.encode(signature);
+        let response = self
+            .retry
+            .retry("joining-code request", || async {
+                self.client
+                    .post(self.endpoint.clone())
+                    .timeout(self.timeout)
+                    .header("X-Signature", &signature)
+                    .json(&payload)
+                    .send()
+                    .await
+                    .context("failed to reach the joining-code service")?
+                    .error_for_status()
+                    .context("joining-code service refused the membrane proof request")
+            })
+            .await?;
+
+        let JoiningCodeResponse {
+            mut membrane_proofs,
+        } = response
+            .json()
+            .await
+            .context("failed to parse joining-code service response")?;
+        trace!("Got membrane proofs for roles {:?}", membrane_proofs.keys());
+
+        roles
+            .into_iter()
+            .map(|role| {
+                let encoded = membrane_proofs.remove(&role).ok_or_else(|| {
+                    anyhow!(
+                        "joining-code service returned no membrane proof for role {} of happ {}",
+                        role,
+                        happ_id
+                    )
+                })?;
+                let proof = BASE64_STANDARD.decode(encoded).with_context(|| {
+                    format!(
+                        "invalid membrane proof for role {} of happ {}",
+                        role, happ_id
+                    )
+                })?;
+                Ok((role, membrane_proof(proof)))
+            })
+            .collect()
+    }
+}
+
+fn membrane_proof(bytes: Vec<u8>) -> MembraneProof {
+    Arc::new(SerializedBytes::from(UnsafeBytes::from(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        fake_host_preferences, no_pending_transactions, write_fake_bundle, FakeHostingRegistry,
+        FakeHttpServer, FakeResponse,
+    };
+    use serde_json::json;
+
+    const HAPP_ID: &str = "uhCkkHappId";
+
+    async fn bundle_url(dir: &tempfile::TempDir) -> String {
+        let path = write_fake_bundle(dir.path(), None).await;
+        Url::from_file_path(path).unwrap().to_string()
+    }
+
+    fn client(server: &FakeHttpServer) -> JoiningCodeClient<FakeHostingRegistry> {
+        let registry = FakeHostingRegistry::new(fake_host_preferences(), no_pending_transactions());
+        JoiningCodeClient::new(server.url("proofs"), Arc::default(), registry)
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn requests_are_signed_with_the_host_agent_key_and_get_a_proof_for_every_role() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = FakeHttpServer::start(|_| {
+            let body = json!({ "membrane_proofs": { "test": BASE64_STANDARD.encode([4, 2]) } });
+            FakeResponse::new(200, body.to_string())
+        })
+        .await;
+        let client = client(&server);
+
+        let proofs = client
+            .get_membrane_proofs(HAPP_ID, &bundle_url(&dir).await)
+            .await
+            .unwrap();
+        assert_eq!(
+            proofs,
+            HashMap::from([("test".to_string(), membrane_proof(vec![4, 2]))])
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            (requests[0].method.as_str(), requests[0].path.as_str()),
+            ("POST", "/proofs")
+        );
+        let registry = client.registry.lock().await;
+        assert_eq!(
+            requests[0].header("X-Signature"),
+            Some(BASE64_STANDARD.encode(&registry.host_signature).as_str())
+        );
+        let payload: JoiningCodeRequest = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(payload.happ_id, HAPP_ID);
+        assert_eq!(payload.roles, vec!["test"]);
+        assert_eq!(
+            payload.agent_pub_key,
+            registry.host_agent_pubkey.to_string()
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn a_response_missing_a_role_gives_no_proofs() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = FakeHttpServer::start(|_| {
+            let body = json!({ "membrane_proofs": { "other": BASE64_STANDARD.encode([4, 2]) } });
+            FakeResponse::new(200, body.to_string())
+        })
+        .await;
+
+        let error = client(&server)
+            .get_membrane_proofs(HAPP_ID, &bundle_url(&dir).await)
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("no membrane proof for role test"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn refused_requests_are_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = FakeHttpServer::start(|_| FakeResponse::new(403, "bad signature")).await;
+
+        let error = client(&server)
+            .get_membrane_proofs(HAPP_ID, &bundle_url(&dir).await)
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("refused the membrane proof request"),
+            "{:#}",
+            error
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+}
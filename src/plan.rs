@@ -0,0 +1,408 @@
+use crate::happ_list::HappList;
+use crate::protection::ProtectionPolicy;
+use crate::suspension::{OverdueInvoice, SuspensionStage};
+use crate::types::{
+    happ::{HappPreferences, KycPriceLimits, PricedResource},
+    hbs::{HostCredentials, KycLevel},
+    HappBundle, PublishedHappDetails,
+};
+use holofuel_types::fuel::Fuel;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, time::Duration};
+
+/// Everything the planner needs to know about hha, hbs and the conductor at the start of a run
+#[derive(Debug, Clone)]
+pub struct ReconciliationSnapshot {
+    /// All happs published for hosting in hha
+    pub published_happs: Vec<HappBundle>,
+    /// The most overdue hosting invoice of each happ that has one, keyed by happ id, including those still within the grace period
+    pub suspended_happs: HashMap<String, OverdueInvoice>,
+    /// The kyc level and jurisdiction of the host, as reported by hbs
+    pub host_credentials: HostCredentials,
+    /// The hosting preferences the host set in hha
+    pub host_happ_preferences: HappPreferences,
+    /// The most a happ may pay a host of each kyc level per unit of any resource
+    pub kyc_price_limits: KycPriceLimits,
+    /// The jurisdiction, categories, and publisher jurisdiction for each published happ, keyed by happ id
+    pub published_happ_details: HashMap<String, PublishedHappDetails>,
+    /// The `installed_app_id`s of all apps currently enabled in the conductor
+    pub enabled_happ_ids: Vec<String>,
+    /// The `installed_app_id`s of all apps currently disabled, or paused after an error, in the conductor
+    pub disabled_happ_ids: Vec<String>,
+    /// The core and self-hosted happs the host listed, which are installed and never removed
+    pub happ_list: HappList,
+    /// Which other enabled apps are never removed
+    pub protection: ProtectionPolicy,
+    /// The `installed_app_id`s of apps a previous run disabled at the disable stage of a suspension,
+    /// which are enabled again once the invoice is paid, or uninstalled once it is overdue for long enough
+    pub suspension_disabled_app_ids: Vec<String>,
+    /// The `installed_app_id`s (and happ ids) the host asked to uninstall, which are not enabled again
+    /// unless the host holo-enables the happ again
+    pub removal_requested_app_ids: Vec<String>,
+}
+
+/// Whether a happ may be hosted on this holoport, and if not, every hosting check it failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Eligibility {
+    Eligible,
+    /// Never empty, in the order the checks are run
+    Ineligible(Vec<Ineligibility>),
+}
+
+impl Eligibility {
+    pub fn is_eligible(&self) -> bool {
+        matches!(self, Eligibility::Eligible)
+    }
+
+    /// The failed checks, empty if the happ is eligible
+    pub fn reasons(&self) -> &[Ineligibility] {
+        match self {
+            Eligibility::Eligible => &[],
+            Eligibility::Ineligible(reasons) => reasons,
+        }
+    }
+}
+
+impl From<&[HostingCheck]> for Eligibility {
+    /// Collects every failure of `checks`, in order
+    fn from(checks: &[HostingCheck]) -> Self {
+        checks
+            .iter()
+            .flat_map(|check| check.failures.iter().cloned())
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+impl From<Vec<Ineligibility>> for Eligibility {
+    fn from(reasons: Vec<Ineligibility>) -> Self {
+        if reasons.is_empty() {
+            Eligibility::Eligible
+        } else {
+            Eligibility::Ineligible(reasons)
+        }
+    }
+}
+
+impl fmt::Display for Eligibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Eligibility::Eligible => f.write_str("happ is eligible for hosting"),
+            Eligibility::Ineligible(reasons) => write!(f, "{}", reasons.iter().join("; ")),
+        }
+    }
+}
+
+/// One of the hosting checks `check_eligibility` runs on a happ, with the values it compared
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostingCheck {
+    pub name: &'static str,
+    /// The happ's and the host's values, as compared by the check
+    pub compared: String,
+    /// Every way the happ failed the check, empty if it passed
+    pub failures: Vec<Ineligibility>,
+}
+
+impl HostingCheck {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// A hosting check a happ failed, with the values it failed on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "check", rename_all = "snake_case")]
+pub enum Ineligibility {
+    /// The happ has a hosting invoice that is overdue past the grace period
+    Suspended {
+        invoice: String,
+        stage: SuspensionStage,
+    },
+    /// The publisher's jurisdiction is not allowed by the host's preferences
+    PublisherJurisdictionRejected {
+        publisher_jurisdiction: Option<String>,
+    },
+    /// The host's jurisdiction is not allowed by the happ
+    HostJurisdictionRejected {
+        host_jurisdiction: Option<String>,
+        happ_jurisdictions: Vec<String>,
+        exclude_happ_jurisdictions: bool,
+    },
+    /// The happ's categories are not allowed by the host's preferences
+    CategoryRejected { categories: Vec<String> },
+    /// The happ pays less for a resource than the host charges for it
+    PriceBelowHostMinimum {
+        resource: PricedResource,
+        happ_price: Fuel,
+        host_price: Fuel,
+    },
+    /// The happ pays more for a resource than a host of the host's kyc level may be paid
+    PriceAboveKycLimit {
+        level: KycLevel,
+        resource: PricedResource,
+        happ_price: Fuel,
+        max_price: Fuel,
+    },
+    /// The happ's publisher is invoiced less often than the host invoices
+    InvoiceTermsRejected {
+        happ_max_fuel_before_invoice: Fuel,
+        host_max_fuel_before_invoice: Fuel,
+        happ_max_time_before_invoice: Duration,
+        host_max_time_before_invoice: Duration,
+    },
+    /// The host's measured uptime is below the minimum the happ requires, or unknown while the happ requires some
+    UptimeInsufficient {
+        min_uptime: f32,
+        host_uptime: Option<f32>,
+    },
+    /// The host disabled the happ in hha
+    HostDisabled,
+    /// The host is not kyc level 2, which is required to host a happ at any price
+    KycInsufficient { level: KycLevel },
+}
+
+impl fmt::Display for Ineligibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ineligibility::Suspended { invoice, stage } => write!(
+                f,
+                "happ is suspended ({} stage) for overdue invoice {}",
+                stage, invoice
+            ),
+            Ineligibility::PublisherJurisdictionRejected {
+                publisher_jurisdiction,
+            } => write!(
+                f,
+                "publisher is in invalid jurisdiction {:?}",
+                publisher_jurisdiction
+            ),
+            Ineligibility::HostJurisdictionRejected {
+                host_jurisdiction,
+                happ_jurisdictions,
+                exclude_happ_jurisdictions,
+            } => write!(
+                f,
+                "host is in invalid jurisdiction {:?} for happ {} {:?}",
+                host_jurisdiction,
+                if *exclude_happ_jurisdictions {
+                    "excluded jurisdictions"
+                } else {
+                    "allowed jurisdictions"
+                },
+                happ_jurisdictions
+            ),
+            Ineligibility::CategoryRejected { categories } => write!(
+                f,
+                "happ categories {:?} are invalid based on host preferences",
+                categories
+            ),
+            Ineligibility::PriceBelowHostMinimum {
+                resource,
+                happ_price,
+                host_price,
+            } => write!(
+                f,
+                "happ {} price {} is below host price {}",
+                resource, happ_price, host_price
+            ),
+            Ineligibility::PriceAboveKycLimit {
+                level,
+                resource,
+                happ_price,
+                max_price,
+            } => write!(
+                f,
+                "happ {} price {} is above the maximum price {} for a host of kyc {:?}",
+                resource, happ_price, max_price, level
+            ),
+            Ineligibility::InvoiceTermsRejected {
+                happ_max_fuel_before_invoice,
+                host_max_fuel_before_invoice,
+                happ_max_time_before_invoice,
+                host_max_time_before_invoice,
+            } => write!(
+                f,
+                "happ is invoiced after {} fuel or {:?}, but host invoices after {} fuel or {:?}",
+                happ_max_fuel_before_invoice,
+                happ_max_time_before_invoice,
+                host_max_fuel_before_invoice,
+                host_max_time_before_invoice
+            ),
+            Ineligibility::UptimeInsufficient {
+                min_uptime,
+                host_uptime: Some(host_uptime),
+            } => write!(
+                f,
+                "host uptime {} is below the happ's minimum uptime {}",
+                host_uptime, min_uptime
+            ),
+            Ineligibility::UptimeInsufficient {
+                min_uptime,
+                host_uptime: None,
+            } => write!(
+                f,
+                "host uptime is unknown, but the happ requires a minimum uptime of {}",
+                min_uptime
+            ),
+            Ineligibility::HostDisabled => f.write_str("host disabled happ in hha"),
+            Ineligibility::KycInsufficient { level } => {
+                write!(f, "host is kyc {:?}, not kyc level 2", level)
+            }
+        }
+    }
+}
+
+/// A single change the installer intends to make on the holoport, either in the conductor or in hha
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HappAction {
+    /// Install and enable the read-only instance of a hosted happ (and its servicelogger) via hpos-api
+    Install {
+        bundle_url: String,
+        /// DNA hashes registered in hha, which the downloaded bundle must match
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        dna_hashes: Vec<String>,
+        /// The core app the happ runs on, in which case only its servicelogger is installed
+        #[serde(skip_serializing_if = "Option::is_none")]
+        special_installed_app_id: Option<String>,
+    },
+    /// Install and enable a core or self-hosted happ from the happ-list file, through the conductor's admin interface
+    InstallListed { bundle_url: String },
+    /// Holochain-enable an app that was disabled in the conductor
+    Enable,
+    /// Holochain-disable the app in the conductor
+    Disable,
+    /// Uninstall the app from the conductor
+    Uninstall,
+    /// Holo-disable the happ in hha for this holoport
+    HoloDisable,
+    /// Holo-enable the happ in hha for this holoport
+    HoloEnable,
+}
+
+impl HappAction {
+    /// Where the action is applied, which decides what its `installed_app_id` identifies
+    pub fn target(&self) -> ActionTarget {
+        match self {
+            HappAction::HoloDisable | HappAction::HoloEnable => ActionTarget::Hha,
+            _ => ActionTarget::Conductor,
+        }
+    }
+}
+
+/// Where an action is applied. The anonymous instance of a hosted happ has the happ id as its `installed_app_id`,
+/// so an action in the conductor and one in hha can target the same id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionTarget {
+    /// An app installed in the conductor, identified by its `installed_app_id`
+    Conductor,
+    /// A happ in hha, identified by its happ id
+    Hha,
+}
+
+impl fmt::Display for HappAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HappAction::Install { .. } | HappAction::InstallListed { .. } => "install",
+            HappAction::Enable => "enable",
+            HappAction::Disable => "disable",
+            HappAction::Uninstall => "uninstall",
+            HappAction::HoloDisable => "holo-disable",
+            HappAction::HoloEnable => "holo-enable",
+        };
+        f.pad(name)
+    }
+}
+
+/// An action together with the app it targets and the reason it was chosen.
+/// For `Install`, `HoloDisable` and `HoloEnable` the `installed_app_id` is the happ id itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedAction {
+    pub installed_app_id: String,
+    pub action: HappAction,
+    pub reason: String,
+}
+
+impl PlannedAction {
+    pub fn new(
+        installed_app_id: impl Into<String>,
+        action: HappAction,
+        reason: impl Into<String>,
+    ) -> Self {
+        PlannedAction {
+            installed_app_id: installed_app_id.into(),
+            action,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<12} {} ({})",
+            self.action, self.installed_app_id, self.reason
+        )
+    }
+}
+
+/// The facts from the snapshot that the actions on one app were decided on
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DecisionInputs {
+    /// The published happ the app is an instance of, if any
+    pub happ_id: Option<String>,
+    pub is_published: bool,
+    pub is_paused: Option<bool>,
+    pub is_host_disabled: Option<bool>,
+    pub is_suspended: bool,
+    /// How far the happ's suspension has gone, if it has an overdue hosting invoice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspension_stage: Option<SuspensionStage>,
+    /// Whether the host asked for the happ to be uninstalled
+    #[serde(default)]
+    pub is_removal_requested: bool,
+    pub host_kyc: KycLevel,
+    pub host_jurisdiction: Option<String>,
+    pub publisher_jurisdiction: Option<String>,
+    #[serde(default)]
+    pub host_uptime: Option<f32>,
+    /// Every hosting check the happ failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ineligibility: Vec<Ineligibility>,
+}
+
+/// The ordered list of actions a run would take to reconcile the conductor with hha and hbs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReconciliationPlan {
+    pub actions: Vec<PlannedAction>,
+    /// The inputs behind the actions, keyed by where they are applied and their `installed_app_id`
+    pub inputs: HashMap<(ActionTarget, String), DecisionInputs>,
+}
+
+impl ReconciliationPlan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// The inputs `planned_action` was decided on
+    pub fn inputs_for(&self, planned_action: &PlannedAction) -> Option<&DecisionInputs> {
+        self.inputs.get(&(
+            planned_action.action.target(),
+            planned_action.installed_app_id.clone(),
+        ))
+    }
+}
+
+impl fmt::Display for ReconciliationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.actions.is_empty() {
+            return writeln!(f, "Nothing to do: holoport is already reconciled");
+        }
+        for action in &self.actions {
+            writeln!(f, "{}", action)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1543 @@
+use crate::happ_list::HappList;
+use crate::plan::{
+    DecisionInputs, Eligibility, HappAction, HostingCheck, Ineligibility, PlannedAction,
+    ReconciliationPlan, ReconciliationSnapshot,
+};
+use crate::protection::{InstalledAppKind, ProtectionPolicy};
+use crate::suspension::{OverdueInvoice, SuspensionStage};
+use crate::types::{
+    happ::{ExclusivePreferences, HappPreferences, KycPriceLimits, PricedResource},
+    hbs::{HostCredentials, KycLevel},
+    HappBundle, PublishedHappDetails,
+};
+use itertools::Itertools;
+use std::collections::HashMap;
+use tracing::{info, trace, warn};
+
+fn is_anonymous_instance(installed_app_id: &str) -> bool {
+    matches!(
+        InstalledAppKind::parse(installed_app_id),
+        InstalledAppKind::Anonymous { .. }
+    )
+}
+
+/// Returns true if `installed_app_id` represents an anonymous or identified instance of `happ_id`
+pub(crate) fn is_instance_of_happ(happ_id: &str, installed_app_id: &str) -> bool {
+    match InstalledAppKind::parse(installed_app_id) {
+        InstalledAppKind::Anonymous {
+            happ_id: instance_of,
+        }
+        | InstalledAppKind::Identified {
+            happ_id: instance_of,
+            ..
+        } => instance_of == happ_id,
+        InstalledAppKind::Core | InstalledAppKind::ServiceLogger { .. } => false,
+    }
+}
+
+/// Returns whether `installed_happ_id`, an instance of `happ_id`, may stay enabled on this holoport,
+/// logging every hosting check it failed
+pub fn should_be_enabled(
+    installed_happ_id: &str,
+    happ_id: &str,
+    suspended_happs: &HashMap<String, OverdueInvoice>,
+    host_credentials: &HostCredentials, // the kyc and jurisdiction of a host
+    host_happ_preferences: &HappPreferences, // the hosting preferences a host sets
+    kyc_price_limits: &KycPriceLimits,  // the most a happ may pay a host of each kyc level
+    published_happ_details: &HashMap<String, PublishedHappDetails>, // the jurisdiction, categories, and publisher jurisdiction for each happ
+) -> Eligibility {
+    trace!(
+        "Running the `should_be_enabled check` for {}",
+        installed_happ_id
+    );
+
+    let eligibility = check_eligibility(
+        happ_id,
+        suspended_happs,
+        host_credentials,
+        host_happ_preferences,
+        kyc_price_limits,
+        published_happ_details,
+    );
+    if !eligibility.is_eligible() {
+        warn!(
+            "Happ {} will be disabled/uninstalled because {}",
+            installed_happ_id, eligibility
+        );
+    }
+    eligibility
+}
+
+/// Runs every hosting check on `happ_id` for this holoport, collecting each one it fails
+pub fn check_eligibility(
+    happ_id: &str,
+    suspended_happs: &HashMap<String, OverdueInvoice>,
+    host_credentials: &HostCredentials,
+    host_happ_preferences: &HappPreferences,
+    kyc_price_limits: &KycPriceLimits,
+    published_happ_details: &HashMap<String, PublishedHappDetails>,
+) -> Eligibility {
+    hosting_checks(
+        happ_id,
+        suspended_happs,
+        host_credentials,
+        host_happ_preferences,
+        kyc_price_limits,
+        published_happ_details,
+    )
+    .as_slice()
+    .into()
+}
+
+/// Runs every hosting check on `happ_id` for this holoport, returning each check in order with the values it compared.
+/// The checks on the happ's registration are skipped if it has no published details.
+pub fn hosting_checks(
+    happ_id: &str,
+    suspended_happs: &HashMap<String, OverdueInvoice>,
+    host_credentials: &HostCredentials,
+    host_happ_preferences: &HappPreferences,
+    kyc_price_limits: &KycPriceLimits,
+    published_happ_details: &HashMap<String, PublishedHappDetails>,
+) -> Vec<HostingCheck> {
+    let mut checks = Vec::new();
+
+    // Happs with invoices still within the grace period stay enabled
+    let overdue_invoice = suspended_happs.get(happ_id);
+    checks.push(HostingCheck {
+        name: "suspension",
+        compared: match overdue_invoice {
+            Some(overdue) => format!(
+                "happ has hosting invoice {} overdue for {} days ({} stage{})",
+                overdue.invoice,
+                overdue.overdue_days(),
+                overdue.stage,
+                if overdue.is_suspended() {
+                    ""
+                } else {
+                    ", within the grace period"
+                }
+            ),
+            None => "happ has no overdue hosting invoices".to_string(),
+        },
+        failures: overdue_invoice
+            .filter(|overdue| overdue.is_suspended())
+            .map(|overdue| Ineligibility::Suspended {
+                invoice: overdue.invoice.clone(),
+                stage: overdue.stage,
+            })
+            .into_iter()
+            .collect(),
+    });
+
+    // Iterate over each happ details to run credentials check between the happ, publisher, and host:
+    if let Some(happ_registration_details) = published_happ_details.get(happ_id) {
+        // Verify that the publisher's jurisdiction matches the host's jurisdiction preferences
+        let mut failures = Vec::new();
+        if !host_happ_preferences.is_happ_publisher_in_valid_jurisdiction(
+            &happ_registration_details.publisher_jurisdiction,
+        ) {
+            // app should not remain installed/enabled if publisher juridiction is invalid
+            failures.push(Ineligibility::PublisherJurisdictionRejected {
+                publisher_jurisdiction: happ_registration_details.publisher_jurisdiction.clone(),
+            });
+        }
+        checks.push(HostingCheck {
+            name: "publisher jurisdiction",
+            compared: format!(
+                "publisher jurisdiction {:?} against host preference {}",
+                happ_registration_details.publisher_jurisdiction,
+                describe_preferences(&host_happ_preferences.jurisdiction_prefs)
+            ),
+            failures,
+        });
+
+        // Verify that the host's jurisdiction matches the app's jurisdiction list - (ie: ensure that the hApp is allowed to run on the host's current jurisdiction)
+        // NB: The host's jurisdiction is taken from mongodb (via hbs)
+        let mut failures = Vec::new();
+        if !host_credentials.is_host_in_valid_jurisdiction(
+            happ_registration_details.should_exclude_happ_jurisdictions,
+            &happ_registration_details.happ_jurisdictions,
+        ) {
+            // app should not remain installed/enabled if host juridiction is invalid
+            failures.push(Ineligibility::HostJurisdictionRejected {
+                host_jurisdiction: host_credentials.jurisdiction.clone(),
+                happ_jurisdictions: happ_registration_details.happ_jurisdictions.clone(),
+                exclude_happ_jurisdictions: happ_registration_details
+                    .should_exclude_happ_jurisdictions,
+            });
+        }
+        checks.push(HostingCheck {
+            name: "host jurisdiction",
+            compared: format!(
+                "host jurisdiction {:?} against happ {} {:?}",
+                host_credentials.jurisdiction,
+                if happ_registration_details.should_exclude_happ_jurisdictions {
+                    "excluded jurisdictions"
+                } else {
+                    "allowed jurisdictions"
+                },
+                happ_registration_details.happ_jurisdictions
+            ),
+            failures,
+        });
+
+        // Verify that the hApp category is a valid host category.
+        let mut failures = Vec::new();
+        if !host_happ_preferences.is_happ_valid_category(&happ_registration_details.happ_categories)
+        {
+            // app should not remain installed/enabled if happ category is invalid
+            failures.push(Ineligibility::CategoryRejected {
+                categories: happ_registration_details.happ_categories.clone(),
+            });
+        };
+        checks.push(HostingCheck {
+            name: "category",
+            compared: format!(
+                "happ categories {:?} against host preference {}",
+                happ_registration_details.happ_categories,
+                describe_preferences(&host_happ_preferences.categories_prefs)
+            ),
+            failures,
+        });
+
+        // Verify that the happ pays at least the host's price for each resource
+        let happ_pricing = &happ_registration_details.happ_pricing;
+        checks.push(HostingCheck {
+            name: "pricing",
+            compared: format!(
+                "happ prices {}/{}/{} against host prices {}/{}/{} (compute/storage/bandwidth)",
+                happ_pricing.price_compute,
+                happ_pricing.price_storage,
+                happ_pricing.price_bandwidth,
+                host_happ_preferences.price_compute,
+                host_happ_preferences.price_storage,
+                host_happ_preferences.price_bandwidth
+            ),
+            failures: host_happ_preferences
+                .resources_priced_below_host(happ_pricing)
+                .into_iter()
+                .map(|resource| Ineligibility::PriceBelowHostMinimum {
+                    resource,
+                    happ_price: happ_pricing.price(resource).clone(),
+                    host_price: host_happ_preferences.price(resource).clone(),
+                })
+                .collect(),
+        });
+
+        // Verify that the happ pays no more for each resource than a host of this kyc level may be paid
+        let max_price = kyc_price_limits.max_price(&host_credentials.kyc);
+        checks.push(HostingCheck {
+            name: "kyc price limit",
+            compared: match max_price {
+                Some(max_price) => format!(
+                    "happ prices {}/{}/{} against maximum price {} for a host of kyc {:?} (compute/storage/bandwidth)",
+                    happ_pricing.price_compute,
+                    happ_pricing.price_storage,
+                    happ_pricing.price_bandwidth,
+                    max_price,
+                    host_credentials.kyc
+                ),
+                None => format!("no maximum price for a host of kyc {:?}", host_credentials.kyc),
+            },
+            failures: max_price
+                .into_iter()
+                .flat_map(|max_price| {
+                    PricedResource::ALL
+                        .into_iter()
+                        .filter(move |resource| happ_pricing.price(*resource) > max_price)
+                        .map(move |resource| Ineligibility::PriceAboveKycLimit {
+                            level: host_credentials.kyc.clone(),
+                            resource,
+                            happ_price: happ_pricing.price(resource).clone(),
+                            max_price: max_price.clone(),
+                        })
+                })
+                .collect(),
+        });
+
+        // Verify that the happ's publisher is invoiced at least as often as the host invoices
+        let mut failures = Vec::new();
+        if !host_happ_preferences.are_valid_invoice_terms(happ_pricing) {
+            failures.push(Ineligibility::InvoiceTermsRejected {
+                happ_max_fuel_before_invoice: happ_pricing.max_fuel_before_invoice.clone(),
+                host_max_fuel_before_invoice: host_happ_preferences.max_fuel_before_invoice.clone(),
+                happ_max_time_before_invoice: happ_pricing.max_time_before_invoice,
+                host_max_time_before_invoice: host_happ_preferences.max_time_before_invoice,
+            });
+        }
+        checks.push(HostingCheck {
+            name: "invoice terms",
+            compared: format!(
+                "happ invoiced after {} fuel or {:?} against host invoicing after {} fuel or {:?}",
+                happ_pricing.max_fuel_before_invoice,
+                happ_pricing.max_time_before_invoice,
+                host_happ_preferences.max_fuel_before_invoice,
+                host_happ_preferences.max_time_before_invoice
+            ),
+            failures,
+        });
+
+        // Verify that the host is online at least as much as the happ requires
+        let mut failures = Vec::new();
+        if !host_credentials.is_host_meeting_uptime(happ_registration_details.min_uptime) {
+            failures.push(Ineligibility::UptimeInsufficient {
+                min_uptime: happ_registration_details.min_uptime,
+                host_uptime: host_credentials.uptime,
+            });
+        }
+        checks.push(HostingCheck {
+            name: "uptime",
+            compared: format!(
+                "host uptime {} against happ minimum uptime {}",
+                host_credentials
+                    .uptime
+                    .map_or("unknown".to_string(), |uptime| uptime.to_string()),
+                happ_registration_details.min_uptime
+            ),
+            failures,
+        });
+
+        // Check whether the expected happ is disabled by the host.
+        checks.push(HostingCheck {
+            name: "host disabled",
+            compared: format!(
+                "host disabled happ in hha: {}",
+                happ_registration_details.is_disabled_by_host
+            ),
+            failures: if happ_registration_details.is_disabled_by_host {
+                vec![Ineligibility::HostDisabled]
+            } else {
+                vec![]
+            },
+        });
+    }
+
+    // NB: Happ-hosting is only valid (despite price prefs) if the host is >= kyc level 2
+    checks.push(HostingCheck {
+        name: "kyc",
+        compared: format!(
+            "host kyc level {:?} against required {:?}",
+            host_credentials.kyc,
+            KycLevel::Level2
+        ),
+        failures: if host_credentials.kyc != KycLevel::Level2 {
+            vec![Ineligibility::KycInsufficient {
+                level: host_credentials.kyc.clone(),
+            }]
+        } else {
+            vec![]
+        },
+    });
+
+    checks
+}
+
+fn describe_preferences(preferences: &Option<ExclusivePreferences>) -> String {
+    match preferences {
+        Some(ExclusivePreferences {
+            value,
+            is_exclusion: true,
+        }) => format!("excluding {:?}", value),
+        Some(ExclusivePreferences {
+            value,
+            is_exclusion: false,
+        }) => format!("allowing only {:?}", value),
+        None => "(none set)".to_string(),
+    }
+}
+
+/// Plans the installation of all happs that are eligible for hosting
+/// and the holochain-disabling of installed happs that were paused by their publisher
+pub fn plan_holo_hosted_happ_installs(
+    enabled_happ_ids: &[String],
+    happs: &[HappBundle],
+    is_kyc_level_2: bool,
+) -> Vec<PlannedAction> {
+    info!("Planning happ installations....");
+
+    let mut actions = Vec::new();
+
+    if happs.is_empty() {
+        info!("No happs registered to be enabled for hosting.");
+        return actions;
+    }
+
+    for HappBundle {
+        happ_id,
+        bundle_url,
+        is_paused,
+        is_host_disabled,
+        special_installed_app_id,
+        exclude_jurisdictions: _,
+        jurisdictions: _,
+        categories: _,
+        host_settings,
+        dna_hashes,
+        min_uptime: _,
+    } in happs
+    {
+        trace!("Checking whether to install {}", happ_id);
+
+        // Currently, the Hosted HoloFuel and Cloud Console happs should have a `special_installed_app_id`.
+        // If happ has a `special_installed_app_id`, the happ relies on the core-app for dna calls.
+        // In this case we only need to confirm that the hosted happ has an enabled sl instance.
+        // If it does have a runnning SL, we consider the app ready for use and and do nothing
+        // ...otherwise, we proceed to install, which leads to the installation of a sl instance for this happ
+        if special_installed_app_id.is_some()
+            && enabled_happ_ids.contains(&format!("{}::servicelogger", happ_id))
+            && host_settings.is_enabled
+        {
+            // Skip the install/enable step
+            // NB: We expect our core-app to already be installed and enabled as we never pause/disable/uninstall it
+            trace!(
+                "Special App {:?} already installed",
+                special_installed_app_id
+            );
+        }
+        // Iterate through all currently enabled apps
+        // (NB: The sole exceptions here are Hosted HoloFuel and Cloud Console, as they should always be caught by the prior condition.)
+        else if enabled_happ_ids.contains(&format!("{}", happ_id)) && host_settings.is_enabled {
+            trace!("App {} already installed", happ_id);
+            // Check if this happ was paused by the publisher in hha and disable it in holochain if so
+            if *is_paused {
+                trace!(
+                    "Found paused happ in holo {} - planning to disable happ on holochain conductor.",
+                    happ_id
+                );
+                actions.push(PlannedAction::new(
+                    happ_id.to_string(),
+                    HappAction::Disable,
+                    "happ was paused by its publisher in hha",
+                ));
+            }
+        }
+        // if the expected happ is disabled by the host, we don't install
+        else if is_host_disabled.to_owned() {
+            trace!(
+                "Skipping happ installation due to host's disabled setting for happ {}",
+                happ_id
+            );
+        }
+        // if kyc_level is not 2 then happ hosting is not allowed and we don't install
+        else if !is_kyc_level_2 {
+            trace!(
+                "Skipping hosting of happ {} due to host's kyc level ",
+                happ_id
+            );
+        } else {
+            // else, install the hosted happ read-only instance
+            // (NB: The read-only instance is an instance of the app that installed with the host agent pubkey and a read-only memproof.)
+            actions.push(PlannedAction::new(
+                happ_id.to_string(),
+                HappAction::Install {
+                    bundle_url: bundle_url.clone(),
+                    dna_hashes: dna_hashes.clone(),
+                    special_installed_app_id: special_installed_app_id.clone(),
+                },
+                "happ is published for hosting and not yet enabled on this holoport",
+            ));
+        }
+    }
+    actions
+}
+
+/// Plans the installation of the listed version of every happ in the happ list that is not enabled yet,
+/// and, once a listed version is enabled, the holochain-disabling of the older versions it replaced
+pub fn plan_listed_happ_installs(
+    enabled_happ_ids: &[String],
+    happ_list: &HappList,
+) -> Vec<PlannedAction> {
+    let mut actions = Vec::new();
+
+    for happ in happ_list.happs() {
+        let installed_app_id = happ.installed_app_id();
+        let other_versions: Vec<&String> = enabled_happ_ids
+            .iter()
+            .unique()
+            .filter(|id| happ.is_any_version(id) && !happ.is_listed_version(id))
+            .collect();
+
+        if enabled_happ_ids.iter().any(|id| happ.is_listed_version(id)) {
+            trace!("Listed happ {} already installed", installed_app_id);
+            for other_version in other_versions {
+                actions.push(PlannedAction::new(
+                    other_version.clone(),
+                    HappAction::Disable,
+                    format!("happ was upgraded to {}", installed_app_id),
+                ));
+            }
+        } else if let Some(dna_url) = &happ.dna_url {
+            let reason = if other_versions.is_empty() {
+                "happ is in the happ list and not yet enabled".to_string()
+            } else {
+                format!("happ list upgrades {:?} to this version", other_versions)
+            };
+            actions.push(PlannedAction::new(
+                installed_app_id,
+                HappAction::InstallListed {
+                    bundle_url: dna_url.to_string(),
+                },
+                reason,
+            ));
+        } else {
+            warn!(
+                "Listed happ {} is not enabled, but has no dna_url to install it from",
+                installed_app_id
+            );
+        }
+    }
+    actions
+}
+
+/// Plans the handling of ineligible happs for 2 cases - identified and anonymous hosted agents:
+///  - Identified: Uninstalls & removes identified instances of ineligible happs
+///  - Anonymous: Disables anonymous instance of ineligible happs
+/// Ineligible Happs = old holo-hosted happs, holo-disabled happs, suspended happs, or happs with one of the following:
+///  - 1. an invalid pricing for kyc level, 2. invalid pricing preference, 3. invalid uptime, or 4. invalid jurisdiction
+/// Happs in the `happ_list` and apps the `protection` policy protects are never removed.
+#[allow(clippy::too_many_arguments)]
+pub fn plan_ineligible_happs(
+    enabled_happ_ids: &[String],
+    happ_list: &HappList,
+    protection: &ProtectionPolicy,
+    suspended_happs: &HashMap<String, OverdueInvoice>,
+    host_credentials: &HostCredentials,
+    host_happ_preferences: &HappPreferences,
+    kyc_price_limits: &KycPriceLimits,
+    published_happ_details: &HashMap<String, PublishedHappDetails>,
+) -> Vec<PlannedAction> {
+    info!("Checking to uninstall happs that were removed from the hosted list....");
+
+    let mut actions = Vec::new();
+    let mut happs_to_holo_disable = Vec::new();
+
+    let published_happ_ids: Vec<&String> = published_happ_details.keys().collect();
+    trace!("published_happ_ids {:?}", published_happ_ids);
+
+    for enabled_happ_id in enabled_happ_ids.iter().unique() {
+        if happ_list.is_listed(enabled_happ_id) || protection.is_protected(enabled_happ_id) {
+            trace!("Keeping protected app {}", enabled_happ_id);
+            continue;
+        }
+
+        // Deteremine if the enabled happ is an instance of a published happ
+        let maybe_hosted_instance_happ_id = published_happ_ids
+            .iter()
+            .copied()
+            .find(|published_happ_id| is_instance_of_happ(published_happ_id, enabled_happ_id));
+
+        // `None` if the happ should remain enabled, otherwise the reason it should not,
+        // and whether it is only suspended at the disable stage
+        let removal = match maybe_hosted_instance_happ_id {
+            Some(happ_id) => {
+                trace!("Found hosted happ instance {:?}", &happ_id);
+
+                match should_be_enabled(
+                    enabled_happ_id,
+                    happ_id,
+                    suspended_happs,
+                    host_credentials,
+                    host_happ_preferences,
+                    kyc_price_limits,
+                    published_happ_details,
+                ) {
+                    Eligibility::Eligible => None,
+                    ineligible => {
+                        let reason = format!(
+                            "happ is no longer eligible for hosting on this holoport: {}",
+                            ineligible
+                        );
+                        let is_disable_stage = is_disable_stage_suspension(&ineligible);
+                        if !is_disable_stage
+                            && !happs_to_holo_disable
+                                .iter()
+                                .any(|(holo_disable_id, _)| holo_disable_id == happ_id)
+                        {
+                            happs_to_holo_disable.push((happ_id.clone(), reason.clone()));
+                        }
+                        Some((reason, is_disable_stage))
+                    }
+                }
+            }
+            // The enabled happ is not a hosted instance of a published happ nor protected, so it shouldn't remain installed/enabled
+            None => Some(("happ is no longer published for hosting".to_string(), false)),
+        };
+
+        let Some((reason, is_disable_stage)) = removal else {
+            // If the happ should remain enabled, we leave the happ status unchanged and continue to next happ
+            info!(
+                "Skipping disabling/uninstalling of {} as it should remain enabled",
+                enabled_happ_id
+            );
+            continue;
+        };
+
+        // If apps should no longer remain enabled, we need to take two steps:
+        // Step 1: disable or uninstall app from Holochain Conductor (depending on instance type)
+        // Anonymous apps are only disabled, never uninstalled, as they are currently use a readonly instance of the host's instance of the app.
+        // Instances of happs at the disable stage of a suspension are only disabled too, so they can be enabled again once the invoice is paid.
+        if is_disable_stage || is_anonymous_instance(enabled_happ_id) {
+            actions.push(PlannedAction::new(
+                enabled_happ_id.clone(),
+                HappAction::Disable,
+                reason,
+            ));
+        } else {
+            actions.push(PlannedAction::new(
+                enabled_happ_id.clone(),
+                HappAction::Uninstall,
+                reason,
+            ));
+        }
+    }
+
+    // Step 2: disable hosted happ in hha (holo hosting)
+    for (happ_id, reason) in happs_to_holo_disable {
+        actions.push(PlannedAction::new(happ_id, HappAction::HoloDisable, reason));
+    }
+
+    actions
+}
+
+/// Whether every reason a happ is ineligible is a suspension at the disable stage
+fn is_disable_stage_suspension(eligibility: &Eligibility) -> bool {
+    !eligibility.is_eligible()
+        && eligibility.reasons().iter().all(|reason| {
+            matches!(
+                reason,
+                Ineligibility::Suspended {
+                    stage: SuspensionStage::Disable,
+                    ..
+                }
+            )
+        })
+}
+
+/// Plans the uninstall stage for apps a previous run disabled at the disable stage of a suspension:
+/// once the invoice is overdue past the uninstall stage, identified instances are uninstalled and the happ is holo-disabled.
+/// Once the invoice is paid, `plan_reenables` enables them again.
+pub fn plan_suspension_stages(
+    enabled_happ_ids: &[String],
+    suspension_disabled_app_ids: &[String],
+    suspended_happs: &HashMap<String, OverdueInvoice>,
+) -> Vec<PlannedAction> {
+    let mut actions = Vec::new();
+    let mut happs_to_holo_disable: Vec<(String, String)> = Vec::new();
+
+    for app_id in suspension_disabled_app_ids.iter().unique() {
+        // An app that was enabled again since is left to the ineligible-happ pass
+        if enabled_happ_ids.contains(app_id) {
+            continue;
+        }
+        let Some(happ_id) = InstalledAppKind::parse(app_id)
+            .happ_id()
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let Some(overdue) = suspended_happs
+            .get(&happ_id)
+            .filter(|overdue| overdue.stage == SuspensionStage::Uninstall)
+        else {
+            continue;
+        };
+
+        let reason = format!(
+            "hosting invoice {} is overdue for {} days",
+            overdue.invoice,
+            overdue.overdue_days()
+        );
+        if !is_anonymous_instance(app_id) {
+            actions.push(PlannedAction::new(
+                app_id.clone(),
+                HappAction::Uninstall,
+                reason.clone(),
+            ));
+        }
+        if !happs_to_holo_disable.iter().any(|(id, _)| id == &happ_id) {
+            happs_to_holo_disable.push((happ_id, reason));
+        }
+    }
+
+    for (happ_id, reason) in happs_to_holo_disable {
+        actions.push(PlannedAction::new(happ_id, HappAction::HoloDisable, reason));
+    }
+
+    actions
+}
+
+/// Plans the enabling of disabled (or paused) instances of published happs that are eligible for hosting again,
+/// eg: once an overdue invoice is paid, the host's preferences changed, or the publisher un-paused the happ,
+/// and the holo-enabling of those happs in hha.
+/// Happs the host asked to uninstall are only enabled again once the host holo-enables them.
+#[allow(clippy::too_many_arguments)]
+pub fn plan_reenables(
+    disabled_happ_ids: &[String],
+    removal_requested_app_ids: &[String],
+    published_happs: &[HappBundle],
+    suspended_happs: &HashMap<String, OverdueInvoice>,
+    host_credentials: &HostCredentials,
+    host_happ_preferences: &HappPreferences,
+    kyc_price_limits: &KycPriceLimits,
+    published_happ_details: &HashMap<String, PublishedHappDetails>,
+) -> Vec<PlannedAction> {
+    let reason = "happ is eligible for hosting again";
+    let mut actions = Vec::new();
+    let mut happs_to_holo_enable = Vec::new();
+
+    for disabled_happ_id in disabled_happ_ids.iter().unique() {
+        let Some(happ) = published_happs
+            .iter()
+            .find(|happ| is_instance_of_happ(&happ.happ_id.to_string(), disabled_happ_id))
+        else {
+            continue;
+        };
+        let happ_id = happ.happ_id.to_string();
+
+        if happ.is_paused {
+            trace!(
+                "Keeping {} disabled as it is paused by its publisher",
+                disabled_happ_id
+            );
+            continue;
+        }
+        let is_removal_requested = removal_requested_app_ids
+            .iter()
+            .any(|id| id == disabled_happ_id || id == &happ_id);
+        if is_removal_requested && !happ.host_settings.is_enabled {
+            trace!(
+                "Keeping {} disabled as the host asked to uninstall it",
+                disabled_happ_id
+            );
+            continue;
+        }
+
+        match check_eligibility(
+            &happ_id,
+            suspended_happs,
+            host_credentials,
+            host_happ_preferences,
+            kyc_price_limits,
+            published_happ_details,
+        ) {
+            Eligibility::Eligible => {
+                actions.push(PlannedAction::new(
+                    disabled_happ_id.clone(),
+                    HappAction::Enable,
+                    reason,
+                ));
+                if !happ.host_settings.is_enabled && !happs_to_holo_enable.contains(&happ_id) {
+                    happs_to_holo_enable.push(happ_id);
+                }
+            }
+            ineligible => trace!(
+                "Keeping {} disabled because {}",
+                disabled_happ_id,
+                ineligible
+            ),
+        }
+    }
+
+    for happ_id in happs_to_holo_enable {
+        actions.push(PlannedAction::new(happ_id, HappAction::HoloEnable, reason));
+    }
+
+    actions
+}
+
+/// Builds the full reconciliation plan from a snapshot: first the installs (and upgrades) of listed happs,
+/// then the installs (and publisher pauses) of hosted happs, the enabling of disabled happs that are eligible again,
+/// and the uninstall stage of suspensions,
+/// then the handling of ineligible happs against the conductor state those installs would leave behind.
+/// This performs no I/O, so every branch can be exercised with a hand-built snapshot.
+pub fn plan_reconciliation(snapshot: &ReconciliationSnapshot) -> ReconciliationPlan {
+    let ReconciliationSnapshot {
+        published_happs,
+        suspended_happs,
+        host_credentials,
+        host_happ_preferences,
+        kyc_price_limits,
+        published_happ_details,
+        enabled_happ_ids,
+        disabled_happ_ids,
+        happ_list,
+        protection,
+        suspension_disabled_app_ids,
+        removal_requested_app_ids,
+    } = snapshot;
+
+    let is_host_kyc_level_2 = host_credentials.kyc == KycLevel::Level2;
+
+    let mut actions = plan_listed_happ_installs(enabled_happ_ids, happ_list);
+
+    let mut reenable_actions = plan_reenables(
+        disabled_happ_ids,
+        removal_requested_app_ids,
+        published_happs,
+        suspended_happs,
+        host_credentials,
+        host_happ_preferences,
+        kyc_price_limits,
+        published_happ_details,
+    );
+    reenable_actions.extend(plan_suspension_stages(
+        enabled_happ_ids,
+        suspension_disabled_app_ids,
+        suspended_happs,
+    ));
+    // Happs failing a hosting check (suspended happs included, until their invoice is paid) are not installed,
+    // as the ineligible-happ pass would only remove them again; happs the host asked to uninstall are not installed
+    // until the host holo-enables them again, and happs being enabled again need no install
+    let is_not_reinstalled = |happ_id: &str| {
+        !check_eligibility(
+            happ_id,
+            suspended_happs,
+            host_credentials,
+            host_happ_preferences,
+            kyc_price_limits,
+            published_happ_details,
+        )
+        .is_eligible()
+            || (removal_requested_app_ids.iter().any(|id| id == happ_id)
+                && !published_happs.iter().any(|happ| {
+                    happ.happ_id.to_string() == happ_id && happ.host_settings.is_enabled
+                }))
+            || reenable_actions.iter().any(|planned| {
+                planned.action == HappAction::Enable && planned.installed_app_id == happ_id
+            })
+    };
+    actions.extend(
+        plan_holo_hosted_happ_installs(enabled_happ_ids, published_happs, is_host_kyc_level_2)
+            .into_iter()
+            .filter(|planned| {
+                !matches!(planned.action, HappAction::Install { .. })
+                    || !is_not_reinstalled(&planned.installed_app_id)
+            }),
+    );
+    actions.extend(reenable_actions);
+
+    // The ineligible-happ pass runs against the conductor as it will be once the first pass has been applied:
+    // newly installed (or enabled again) happs are enabled, and happs paused by their publisher are disabled.
+    let mut enabled_after_installs: Vec<String> = enabled_happ_ids.to_vec();
+    for PlannedAction {
+        installed_app_id,
+        action,
+        ..
+    } in actions.iter()
+    {
+        match action {
+            HappAction::Install { .. } | HappAction::InstallListed { .. } | HappAction::Enable => {
+                enabled_after_installs.push(installed_app_id.clone())
+            }
+            HappAction::Disable => enabled_after_installs.retain(|id| id != installed_app_id),
+            HappAction::Uninstall | HappAction::HoloDisable | HappAction::HoloEnable => {}
+        }
+    }
+
+    for planned in plan_ineligible_happs(
+        &enabled_after_installs,
+        happ_list,
+        protection,
+        suspended_happs,
+        host_credentials,
+        host_happ_preferences,
+        kyc_price_limits,
+        published_happ_details,
+    ) {
+        // The suspension pass may already holo-disable the happ
+        if planned.action == HappAction::HoloDisable
+            && actions.iter().any(|planned_before| {
+                planned_before.action == HappAction::HoloDisable
+                    && planned_before.installed_app_id == planned.installed_app_id
+            })
+        {
+            continue;
+        }
+        actions.push(planned);
+    }
+
+    let inputs = actions
+        .iter()
+        .map(
+            |PlannedAction {
+                 installed_app_id,
+                 action,
+                 ..
+             }| {
+                (
+                    (action.target(), installed_app_id.clone()),
+                    decision_inputs(snapshot, installed_app_id),
+                )
+            },
+        )
+        .collect();
+
+    ReconciliationPlan { actions, inputs }
+}
+
+/// Plans the removal of a happ the host asked to stop hosting: every enabled instance of `happ_id` is uninstalled
+/// (or disabled, for the anonymous instance), and the happ is holo-disabled in hha so that later runs do not reinstall it
+pub fn plan_happ_uninstall(snapshot: &ReconciliationSnapshot, happ_id: &str) -> ReconciliationPlan {
+    let reason = "uninstall was requested by the host";
+    let mut actions: Vec<PlannedAction> = snapshot
+        .enabled_happ_ids
+        .iter()
+        .unique()
+        .filter(|enabled_happ_id| is_instance_of_happ(happ_id, enabled_happ_id))
+        .map(|enabled_happ_id| {
+            let action = if is_anonymous_instance(enabled_happ_id) {
+                HappAction::Disable
+            } else {
+                HappAction::Uninstall
+            };
+            PlannedAction::new(enabled_happ_id.clone(), action, reason)
+        })
+        .collect();
+    actions.push(PlannedAction::new(happ_id, HappAction::HoloDisable, reason));
+
+    // Recorded as removal requested so that later runs do not enable the happ again
+    let inputs = actions
+        .iter()
+        .map(|planned| {
+            (
+                (planned.action.target(), planned.installed_app_id.clone()),
+                DecisionInputs {
+                    happ_id: Some(happ_id.to_string()),
+                    is_removal_requested: true,
+                    ..decision_inputs(snapshot, &planned.installed_app_id)
+                },
+            )
+        })
+        .collect();
+
+    ReconciliationPlan { actions, inputs }
+}
+
+/// Collects the facts from the snapshot that decisions about `installed_app_id` are made on
+pub fn decision_inputs(
+    snapshot: &ReconciliationSnapshot,
+    installed_app_id: &str,
+) -> DecisionInputs {
+    let happ = snapshot
+        .published_happs
+        .iter()
+        .find(|happ| is_instance_of_happ(&happ.happ_id.to_string(), installed_app_id));
+    let happ_id = happ.map(|happ| happ.happ_id.to_string());
+    let overdue = happ_id
+        .as_ref()
+        .and_then(|happ_id| snapshot.suspended_happs.get(happ_id));
+
+    DecisionInputs {
+        is_published: happ.is_some(),
+        is_paused: happ.map(|happ| happ.is_paused),
+        is_host_disabled: happ.map(|happ| happ.is_host_disabled),
+        is_suspended: overdue.is_some_and(|overdue| overdue.is_suspended()),
+        suspension_stage: overdue.map(|overdue| overdue.stage),
+        is_removal_requested: snapshot
+            .removal_requested_app_ids
+            .iter()
+            .any(|id| id == installed_app_id || Some(id) == happ_id.as_ref()),
+        host_kyc: snapshot.host_credentials.kyc.clone(),
+        host_jurisdiction: snapshot.host_credentials.jurisdiction.clone(),
+        host_uptime: snapshot.host_credentials.uptime,
+        publisher_jurisdiction: happ_id
+            .as_ref()
+            .and_then(|happ_id| snapshot.published_happ_details.get(happ_id))
+            .and_then(|details| details.publisher_jurisdiction.clone()),
+        ineligibility: happ_id
+            .as_ref()
+            .map(|happ_id| {
+                check_eligibility(
+                    happ_id,
+                    &snapshot.suspended_happs,
+                    &snapshot.host_credentials,
+                    &snapshot.host_happ_preferences,
+                    &snapshot.kyc_price_limits,
+                    &snapshot.published_happ_details,
+                )
+                .reasons()
+                .to_vec()
+            })
+            .unwrap_or_default(),
+        happ_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::happ_list::ListedHapp;
+    use crate::test_support::{
+        fake_happ, fake_happ_id, fake_host_preferences, fake_snapshot, fuel,
+    };
+    use crate::types::happ::HappPricing;
+
+    fn happ_id(n: u8) -> String {
+        fake_happ_id(n).to_string()
+    }
+
+    fn eligibility(snapshot: &ReconciliationSnapshot, happ_id: &str) -> Eligibility {
+        check_eligibility(
+            happ_id,
+            &snapshot.suspended_happs,
+            &snapshot.host_credentials,
+            &snapshot.host_happ_preferences,
+            &snapshot.kyc_price_limits,
+            &snapshot.published_happ_details,
+        )
+    }
+
+    fn details_of<'a>(
+        snapshot: &'a mut ReconciliationSnapshot,
+        happ_id: &str,
+    ) -> &'a mut PublishedHappDetails {
+        snapshot
+            .published_happ_details
+            .get_mut(happ_id)
+            .expect("happ is published")
+    }
+
+    fn pricing_of<'a>(
+        snapshot: &'a mut ReconciliationSnapshot,
+        happ_id: &str,
+    ) -> &'a mut HappPricing {
+        &mut details_of(snapshot, happ_id).happ_pricing
+    }
+
+    fn overdue(stage: SuspensionStage) -> OverdueInvoice {
+        OverdueInvoice {
+            invoice: "invoice".to_string(),
+            overdue_for: std::time::Duration::from_secs(24 * 60 * 60),
+            stage,
+        }
+    }
+
+    /// The planned actions and the apps they target, in order
+    fn actions(plan: &ReconciliationPlan) -> Vec<(String, HappAction)> {
+        plan.actions
+            .iter()
+            .map(|planned| (planned.installed_app_id.clone(), planned.action.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn installs_eligible_happs_that_are_not_enabled() {
+        let happ = fake_happ(1);
+        let snapshot = fake_snapshot(vec![happ.clone()]);
+
+        let plan = plan_reconciliation(&snapshot);
+        assert_eq!(
+            actions(&plan),
+            vec![(
+                happ_id(1),
+                HappAction::Install {
+                    bundle_url: happ.bundle_url,
+                    dna_hashes: vec![],
+                    special_installed_app_id: None,
+                }
+            )]
+        );
+        let inputs = plan.inputs_for(&plan.actions[0]).expect("inputs recorded");
+        assert_eq!(inputs.happ_id, Some(happ_id(1)));
+        assert!(inputs.ineligibility.is_empty());
+    }
+
+    #[test]
+    fn happs_on_a_core_app_are_installed_until_their_servicelogger_is_enabled() {
+        let mut happ = fake_happ(1);
+        happ.special_installed_app_id = Some("core-app:0_2_1".to_string());
+        let mut snapshot = fake_snapshot(vec![happ.clone()]);
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![(
+                happ_id(1),
+                HappAction::Install {
+                    bundle_url: happ.bundle_url,
+                    dna_hashes: vec![],
+                    special_installed_app_id: Some("core-app:0_2_1".to_string()),
+                }
+            )]
+        );
+
+        snapshot.enabled_happ_ids = vec![format!("{}::servicelogger", happ_id(1))];
+        assert!(plan_reconciliation(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn happs_are_not_installed_on_hosts_below_kyc_level_2_or_when_host_disabled() {
+        let mut host_disabled = fake_happ(2);
+        host_disabled.is_host_disabled = true;
+        let mut snapshot = fake_snapshot(vec![fake_happ(1), host_disabled]);
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot))
+                .into_iter()
+                .map(|(installed_app_id, _)| installed_app_id)
+                .collect::<Vec<_>>(),
+            vec![happ_id(1)]
+        );
+
+        snapshot.host_credentials.kyc = KycLevel::Level1;
+        assert!(plan_reconciliation(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn happs_paused_by_their_publisher_are_disabled() {
+        let mut paused = fake_happ(1);
+        paused.is_paused = true;
+        let mut snapshot = fake_snapshot(vec![paused]);
+        snapshot.enabled_happ_ids = vec![happ_id(1)];
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![(happ_id(1), HappAction::Disable)]
+        );
+
+        // Once disabled, it is not enabled again while paused
+        snapshot.enabled_happ_ids = vec![];
+        snapshot.disabled_happ_ids = vec![happ_id(1)];
+        assert!(!actions(&plan_reconciliation(&snapshot))
+            .iter()
+            .any(|(_, action)| *action == HappAction::Enable));
+    }
+
+    #[test]
+    fn ineligible_happs_are_not_installed() {
+        let mut snapshot = fake_snapshot(vec![fake_happ(1), fake_happ(2)]);
+        pricing_of(&mut snapshot, &happ_id(2)).price_storage = fuel("0.5");
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot))
+                .into_iter()
+                .map(|(installed_app_id, action)| (installed_app_id, action.to_string()))
+                .collect::<Vec<_>>(),
+            vec![(happ_id(1), "install".to_string())]
+        );
+    }
+
+    #[test]
+    fn happs_priced_above_the_limit_for_the_host_kyc_level_are_removed() {
+        let happ_id = happ_id(1);
+        let instance_id = format!("{}::agent", happ_id);
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.enabled_happ_ids = vec![happ_id.clone(), instance_id.clone()];
+        snapshot.kyc_price_limits = KycPriceLimits {
+            level_1: Some(fuel("10")),
+            level_2: Some(fuel("2")),
+        };
+        let pricing = pricing_of(&mut snapshot, &happ_id);
+        pricing.price_compute = fuel("2");
+        pricing.price_storage = fuel("3");
+
+        assert_eq!(
+            eligibility(&snapshot, &happ_id),
+            Eligibility::Ineligible(vec![Ineligibility::PriceAboveKycLimit {
+                level: KycLevel::Level2,
+                resource: PricedResource::Storage,
+                happ_price: fuel("3"),
+                max_price: fuel("2"),
+            }])
+        );
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![
+                (happ_id.clone(), HappAction::Disable),
+                (instance_id, HappAction::Uninstall),
+                (happ_id, HappAction::HoloDisable),
+            ]
+        );
+    }
+
+    #[test]
+    fn hosts_with_unknown_uptime_only_host_happs_that_require_none() {
+        let mut requiring = fake_happ(1);
+        requiring.min_uptime = 0.9;
+        let mut snapshot = fake_snapshot(vec![requiring, fake_happ(2)]);
+        snapshot.host_credentials.uptime = None;
+
+        assert_eq!(
+            eligibility(&snapshot, &happ_id(1)),
+            Eligibility::Ineligible(vec![Ineligibility::UptimeInsufficient {
+                min_uptime: 0.9,
+                host_uptime: None,
+            }])
+        );
+        assert_eq!(eligibility(&snapshot, &happ_id(2)), Eligibility::Eligible);
+    }
+
+    #[test]
+    fn happs_the_host_asked_to_uninstall_are_only_reinstalled_once_holo_enabled() {
+        let happ_id = happ_id(1);
+        let mut removed = fake_happ(1);
+        removed.host_settings.is_enabled = false;
+        let mut snapshot = fake_snapshot(vec![removed]);
+        snapshot.removal_requested_app_ids = vec![happ_id.clone()];
+
+        assert!(plan_reconciliation(&snapshot).is_empty());
+
+        snapshot.published_happs[0].host_settings.is_enabled = true;
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot))
+                .into_iter()
+                .map(|(installed_app_id, action)| (installed_app_id, action.to_string()))
+                .collect::<Vec<_>>(),
+            vec![(happ_id, "install".to_string())]
+        );
+    }
+
+    #[test]
+    fn kyc_price_limits_only_apply_to_hosts_of_their_level() {
+        let happ_id = happ_id(1);
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.kyc_price_limits = KycPriceLimits {
+            level_1: Some(fuel("1")),
+            level_2: None,
+        };
+        pricing_of(&mut snapshot, &happ_id).price_bandwidth = fuel("100");
+
+        assert_eq!(eligibility(&snapshot, &happ_id), Eligibility::Eligible);
+    }
+
+    #[test]
+    fn actions_on_happs_the_host_asked_to_uninstall_record_the_removal_request() {
+        let happ_id = happ_id(1);
+        let instance_id = format!("{}::agent", happ_id);
+        let mut removed = fake_happ(1);
+        removed.is_host_disabled = true;
+        removed.host_settings.is_enabled = false;
+        let mut snapshot = fake_snapshot(vec![removed]);
+        snapshot.enabled_happ_ids = vec![instance_id.clone()];
+        snapshot.removal_requested_app_ids = vec![happ_id.clone()];
+
+        let plan = plan_reconciliation(&snapshot);
+        assert_eq!(
+            actions(&plan),
+            vec![
+                (instance_id, HappAction::Uninstall),
+                (happ_id.clone(), HappAction::HoloDisable),
+            ]
+        );
+        for planned in &plan.actions {
+            let inputs = plan.inputs_for(planned).expect("inputs are recorded");
+            assert!(inputs.is_removal_requested);
+            assert_eq!(inputs.happ_id.as_ref(), Some(&happ_id));
+            assert_eq!(inputs.ineligibility, vec![Ineligibility::HostDisabled]);
+        }
+    }
+
+    #[test]
+    fn uninstalls_requested_by_the_host_record_the_hosting_facts_of_the_happ() {
+        let happ_id = happ_id(1);
+        let instance_id = format!("{}::agent", happ_id);
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.enabled_happ_ids = vec![happ_id.clone(), instance_id.clone()];
+
+        let plan = plan_happ_uninstall(&snapshot, &happ_id);
+        assert_eq!(
+            actions(&plan),
+            vec![
+                (happ_id.clone(), HappAction::Disable),
+                (instance_id, HappAction::Uninstall),
+                (happ_id.clone(), HappAction::HoloDisable),
+            ]
+        );
+        for planned in &plan.actions {
+            let inputs = plan.inputs_for(planned).expect("inputs are recorded");
+            assert!(inputs.is_removal_requested);
+            assert!(inputs.is_published);
+            assert_eq!(inputs.happ_id.as_ref(), Some(&happ_id));
+            assert_eq!(inputs.host_kyc, KycLevel::Level2);
+            assert_eq!(inputs.host_jurisdiction.as_deref(), Some("DE"));
+            assert_eq!(inputs.publisher_jurisdiction.as_deref(), Some("DE"));
+        }
+    }
+
+    #[test]
+    fn listed_happs_are_installed_and_then_replace_their_older_versions() {
+        let mut snapshot = fake_snapshot(vec![]);
+        snapshot.happ_list = HappList {
+            core_happs: vec![ListedHapp {
+                app_id: "core-app".to_string(),
+                version: "0_2_1".to_string(),
+                dna_url: Some("https://bundles.holo.host/core-app.happ".parse().unwrap()),
+                ui_url: None,
+            }],
+            self_hosted_happs: vec![],
+        };
+        snapshot.enabled_happ_ids = vec!["core-app:0_2_0".to_string()];
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![(
+                "core-app:0_2_1".to_string(),
+                HappAction::InstallListed {
+                    bundle_url: "https://bundles.holo.host/core-app.happ".to_string(),
+                }
+            )]
+        );
+
+        // Once the listed version is enabled, the version it replaced is disabled
+        snapshot.enabled_happ_ids =
+            vec!["core-app:0_2_0".to_string(), "core-app:0_2_1".to_string()];
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![("core-app:0_2_0".to_string(), HappAction::Disable)]
+        );
+    }
+
+    #[test]
+    fn protected_apps_are_kept_and_other_unpublished_apps_are_removed() {
+        let mut snapshot = fake_snapshot(vec![]);
+        snapshot.protection = ProtectionPolicy {
+            allow: vec![format!("{}::*", happ_id(3)).parse().unwrap()],
+            deny: vec!["custom-app".parse().unwrap()],
+        };
+        snapshot.enabled_happ_ids = vec![
+            "core-app:0_2_0".to_string(),
+            format!("{}::servicelogger", happ_id(2)),
+            format!("{}::agent", happ_id(3)),
+            "custom-app".to_string(),
+            format!("{}::agent", happ_id(4)),
+        ];
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![
+                ("custom-app".to_string(), HappAction::Uninstall),
+                (format!("{}::agent", happ_id(4)), HappAction::Uninstall),
+            ]
+        );
+    }
+
+    #[test]
+    fn each_failed_hosting_check_is_reported() {
+        let cases: [(fn(&mut ReconciliationSnapshot), Ineligibility); 10] = [
+            (
+                |snapshot| {
+                    snapshot
+                        .suspended_happs
+                        .insert(happ_id(1), overdue(SuspensionStage::Disable));
+                },
+                Ineligibility::Suspended {
+                    invoice: "invoice".to_string(),
+                    stage: SuspensionStage::Disable,
+                },
+            ),
+            (
+                |snapshot| {
+                    snapshot.host_happ_preferences.jurisdiction_prefs =
+                        Some(ExclusivePreferences {
+                            value: vec!["DE".to_string()],
+                            is_exclusion: true,
+                        });
+                },
+                Ineligibility::PublisherJurisdictionRejected {
+                    publisher_jurisdiction: Some("DE".to_string()),
+                },
+            ),
+            (
+                |snapshot| {
+                    details_of(snapshot, &happ_id(1)).happ_jurisdictions = vec!["DE".to_string()];
+                },
+                Ineligibility::HostJurisdictionRejected {
+                    host_jurisdiction: Some("DE".to_string()),
+                    happ_jurisdictions: vec!["DE".to_string()],
+                    exclude_happ_jurisdictions: true,
+                },
+            ),
+            (
+                |snapshot| {
+                    details_of(snapshot, &happ_id(1)).happ_categories = vec!["games".to_string()];
+                    snapshot.host_happ_preferences.categories_prefs = Some(ExclusivePreferences {
+                        value: vec!["games".to_string()],
+                        is_exclusion: true,
+                    });
+                },
+                Ineligibility::CategoryRejected {
+                    categories: vec!["games".to_string()],
+                },
+            ),
+            (
+                |snapshot| pricing_of(snapshot, &happ_id(1)).price_compute = fuel("0.5"),
+                Ineligibility::PriceBelowHostMinimum {
+                    resource: PricedResource::Compute,
+                    happ_price: fuel("0.5"),
+                    host_price: fuel("1"),
+                },
+            ),
+            (
+                |snapshot| {
+                    snapshot.kyc_price_limits.level_2 = Some(fuel("2"));
+                    pricing_of(snapshot, &happ_id(1)).price_bandwidth = fuel("3");
+                },
+                Ineligibility::PriceAboveKycLimit {
+                    level: KycLevel::Level2,
+                    resource: PricedResource::Bandwidth,
+                    happ_price: fuel("3"),
+                    max_price: fuel("2"),
+                },
+            ),
+            (
+                |snapshot| pricing_of(snapshot, &happ_id(1)).max_fuel_before_invoice = fuel("2000"),
+                Ineligibility::InvoiceTermsRejected {
+                    happ_max_fuel_before_invoice: fuel("2000"),
+                    host_max_fuel_before_invoice: fuel("1000"),
+                    happ_max_time_before_invoice: fake_host_preferences().max_time_before_invoice,
+                    host_max_time_before_invoice: fake_host_preferences().max_time_before_invoice,
+                },
+            ),
+            (
+                |snapshot| {
+                    details_of(snapshot, &happ_id(1)).min_uptime = 0.99;
+                    snapshot.host_credentials.uptime = Some(0.9);
+                },
+                Ineligibility::UptimeInsufficient {
+                    min_uptime: 0.99,
+                    host_uptime: Some(0.9),
+                },
+            ),
+            (
+                |snapshot| details_of(snapshot, &happ_id(1)).is_disabled_by_host = true,
+                Ineligibility::HostDisabled,
+            ),
+            (
+                |snapshot| snapshot.host_credentials.kyc = KycLevel::Level1,
+                Ineligibility::KycInsufficient {
+                    level: KycLevel::Level1,
+                },
+            ),
+        ];
+
+        assert_eq!(
+            eligibility(&fake_snapshot(vec![fake_happ(1)]), &happ_id(1)),
+            Eligibility::Eligible
+        );
+        for (fail_check, reason) in cases {
+            let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+            fail_check(&mut snapshot);
+            assert_eq!(
+                eligibility(&snapshot, &happ_id(1)),
+                Eligibility::Ineligible(vec![reason.clone()]),
+                "expected only {:?} to fail",
+                reason
+            );
+        }
+    }
+
+    #[test]
+    fn happs_within_the_suspension_grace_period_stay_enabled() {
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.enabled_happ_ids = vec![happ_id(1)];
+        snapshot
+            .suspended_happs
+            .insert(happ_id(1), overdue(SuspensionStage::Warn));
+
+        assert_eq!(eligibility(&snapshot, &happ_id(1)), Eligibility::Eligible);
+        assert!(plan_reconciliation(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn happs_at_the_disable_stage_of_a_suspension_are_only_disabled() {
+        let agent_instance = format!("{}::agent", happ_id(1));
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.enabled_happ_ids = vec![happ_id(1), agent_instance.clone()];
+        snapshot
+            .suspended_happs
+            .insert(happ_id(1), overdue(SuspensionStage::Disable));
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![
+                (happ_id(1), HappAction::Disable),
+                (agent_instance, HappAction::Disable),
+            ]
+        );
+    }
+
+    #[test]
+    fn happs_at_the_uninstall_stage_of_a_suspension_are_removed() {
+        let agent_instance = format!("{}::agent", happ_id(1));
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.enabled_happ_ids = vec![happ_id(1), agent_instance.clone()];
+        snapshot
+            .suspended_happs
+            .insert(happ_id(1), overdue(SuspensionStage::Uninstall));
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![
+                (happ_id(1), HappAction::Disable),
+                (agent_instance, HappAction::Uninstall),
+                (happ_id(1), HappAction::HoloDisable),
+            ]
+        );
+    }
+
+    #[test]
+    fn happs_disabled_for_a_suspension_are_uninstalled_once_it_reaches_the_uninstall_stage() {
+        let agent_instance = format!("{}::agent", happ_id(1));
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.disabled_happ_ids = vec![happ_id(1), agent_instance.clone()];
+        snapshot.suspension_disabled_app_ids = snapshot.disabled_happ_ids.clone();
+        snapshot
+            .suspended_happs
+            .insert(happ_id(1), overdue(SuspensionStage::Uninstall));
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![
+                (agent_instance, HappAction::Uninstall),
+                (happ_id(1), HappAction::HoloDisable),
+            ]
+        );
+    }
+
+    #[test]
+    fn happs_disabled_for_a_suspension_are_enabled_again_once_the_invoice_is_paid() {
+        let agent_instance = format!("{}::agent", happ_id(1));
+        let mut snapshot = fake_snapshot(vec![fake_happ(1)]);
+        snapshot.disabled_happ_ids = vec![happ_id(1), agent_instance.clone()];
+        snapshot.suspension_disabled_app_ids = snapshot.disabled_happ_ids.clone();
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![
+                (happ_id(1), HappAction::Enable),
+                (agent_instance, HappAction::Enable),
+            ]
+        );
+    }
+
+    #[test]
+    fn happs_eligible_again_are_holo_enabled_if_the_host_had_disabled_them() {
+        let mut happ = fake_happ(1);
+        happ.host_settings.is_enabled = false;
+        let mut snapshot = fake_snapshot(vec![happ]);
+        snapshot.disabled_happ_ids = vec![happ_id(1)];
+
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![
+                (happ_id(1), HappAction::Enable),
+                (happ_id(1), HappAction::HoloEnable),
+            ]
+        );
+    }
+
+    #[test]
+    fn happs_the_host_asked_to_uninstall_stay_disabled_until_holo_enabled() {
+        let mut removed = fake_happ(1);
+        removed.host_settings.is_enabled = false;
+        let mut snapshot = fake_snapshot(vec![removed]);
+        snapshot.disabled_happ_ids = vec![happ_id(1)];
+        snapshot.removal_requested_app_ids = vec![happ_id(1)];
+
+        assert!(plan_reconciliation(&snapshot).is_empty());
+
+        snapshot.published_happs[0].host_settings.is_enabled = true;
+        assert_eq!(
+            actions(&plan_reconciliation(&snapshot)),
+            vec![(happ_id(1), HappAction::Enable)]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Result};
+use holochain_types::dna::ActionHashB64;
+use std::fmt;
+use std::str::FromStr;
+
+/// What an `installed_app_id` in the conductor is an instance of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstalledAppKind {
+    /// Any app that is not named after a hosted happ, eg: `core-app:0_2_1` or `servicelogger:0_2_1::<uuid>`
+    Core,
+    /// The read-only instance of a hosted happ, installed as the happ id
+    Anonymous { happ_id: String },
+    /// The instance of a hosted happ for one agent, installed as `<happ id>::<agent>`
+    Identified { happ_id: String, agent: String },
+    /// The servicelogger of a hosted happ, installed as `<happ id>::servicelogger`
+    ServiceLogger { happ_id: String },
+}
+
+impl InstalledAppKind {
+    /// Parses `installed_app_id` by whether it starts with a valid happ id (ie: an action hash),
+    /// and what follows it, rather than by its prefix or length
+    pub fn parse(installed_app_id: &str) -> Self {
+        let (happ_id, suffix) = match installed_app_id.split_once("::") {
+            Some((happ_id, suffix)) => (happ_id, Some(suffix)),
+            None => (installed_app_id, None),
+        };
+        if ActionHashB64::from_b64_str(happ_id).is_err() {
+            return InstalledAppKind::Core;
+        }
+
+        let happ_id = happ_id.to_string();
+        match suffix {
+            None => InstalledAppKind::Anonymous { happ_id },
+            Some("servicelogger") => InstalledAppKind::ServiceLogger { happ_id },
+            Some(agent) => InstalledAppKind::Identified {
+                happ_id,
+                agent: agent.to_string(),
+            },
+        }
+    }
+
+    /// The id of the hosted happ this is an instance (or the servicelogger) of
+    pub fn happ_id(&self) -> Option<&str> {
+        match self {
+            InstalledAppKind::Core => None,
+            InstalledAppKind::Anonymous { happ_id }
+            | InstalledAppKind::Identified { happ_id, .. }
+            | InstalledAppKind::ServiceLogger { happ_id } => Some(happ_id),
+        }
+    }
+}
+
+/// An app id, or a pattern in which `*` matches any run of characters (eg: `core-app:*`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppIdPattern(String);
+
+impl AppIdPattern {
+    pub fn matches(&self, installed_app_id: &str) -> bool {
+        let mut parts = self.0.split('*');
+        let Some(mut rest) = installed_app_id.strip_prefix(parts.next().unwrap_or_default()) else {
+            return false;
+        };
+        let parts: Vec<&str> = parts.collect();
+        let Some((last, middle)) = parts.split_last() else {
+            // No `*`, so the pattern is the whole id
+            return rest.is_empty();
+        };
+        for part in middle {
+            match rest.find(part) {
+                Some(index) => rest = &rest[index + part.len()..],
+                None => return false,
+            }
+        }
+        rest.ends_with(last)
+    }
+}
+
+impl FromStr for AppIdPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim().is_empty() {
+            return Err(anyhow!("App id pattern must not be empty"));
+        }
+        Ok(AppIdPattern(s.trim().to_string()))
+    }
+}
+
+impl fmt::Display for AppIdPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Which enabled apps the ineligible-happ pass must never disable or uninstall.
+/// Apps matching `allow` are protected, then apps matching `deny` are not,
+/// and any other app is protected if it is a core app or a servicelogger.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtectionPolicy {
+    pub allow: Vec<AppIdPattern>,
+    pub deny: Vec<AppIdPattern>,
+}
+
+impl ProtectionPolicy {
+    pub fn is_protected(&self, installed_app_id: &str) -> bool {
+        if self
+            .allow
+            .iter()
+            .any(|pattern| pattern.matches(installed_app_id))
+        {
+            return true;
+        }
+        if self
+            .deny
+            .iter()
+            .any(|pattern| pattern.matches(installed_app_id))
+        {
+            return false;
+        }
+        matches!(
+            InstalledAppKind::parse(installed_app_id),
+            InstalledAppKind::Core | InstalledAppKind::ServiceLogger { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fake_happ_id;
+
+    fn pattern(pattern: &str) -> AppIdPattern {
+        pattern.parse().expect("valid pattern")
+    }
+
+    #[test]
+    fn installed_app_ids_are_parsed_by_the_happ_id_they_start_with() {
+        let happ_id = fake_happ_id(1).to_string();
+        assert_eq!(
+            InstalledAppKind::parse(&happ_id),
+            InstalledAppKind::Anonymous {
+                happ_id: happ_id.clone()
+            }
+        );
+        assert_eq!(
+            InstalledAppKind::parse(&format!("{}::servicelogger", happ_id)),
+            InstalledAppKind::ServiceLogger {
+                happ_id: happ_id.clone()
+            }
+        );
+        assert_eq!(
+            InstalledAppKind::parse(&format!("{}::uhCAkAgent", happ_id)),
+            InstalledAppKind::Identified {
+                happ_id: happ_id.clone(),
+                agent: "uhCAkAgent".to_string()
+            }
+        );
+        for core in [
+            "core-app:0_2_1",
+            "servicelogger:0_2_1::some-uuid",
+            // Prefixed like a happ id, but not one
+            "uhCkkNotAHappId::agent",
+        ] {
+            assert_eq!(InstalledAppKind::parse(core), InstalledAppKind::Core);
+        }
+    }
+
+    #[test]
+    fn patterns_match_whole_app_ids_with_any_run_of_characters_for_a_wildcard() {
+        assert!(pattern("core-app:0_2_1").matches("core-app:0_2_1"));
+        assert!(!pattern("core-app").matches("core-app:0_2_1"));
+        assert!(pattern("core-app:*").matches("core-app:0_2_1"));
+        assert!(pattern("*::servicelogger").matches("happ::servicelogger"));
+        assert!(pattern("a*b*c").matches("a-b-c"));
+        assert!(!pattern("a*b*c").matches("a-c-b"));
+        assert!(pattern(" core-app ").matches("core-app"));
+        assert!(" ".parse::<AppIdPattern>().is_err());
+    }
+
+    #[test]
+    fn allowed_apps_are_protected_even_if_denied_and_denied_apps_are_not_even_if_core() {
+        let happ_id = fake_happ_id(1).to_string();
+        let default = ProtectionPolicy::default();
+        assert!(default.is_protected("core-app:0_2_1"));
+        assert!(default.is_protected(&format!("{}::servicelogger", happ_id)));
+        assert!(!default.is_protected(&happ_id));
+        assert!(!default.is_protected(&format!("{}::agent", happ_id)));
+
+        let policy = ProtectionPolicy {
+            allow: vec![pattern(&format!("{}::*", happ_id))],
+            deny: vec![pattern("*")],
+        };
+        assert!(policy.is_protected(&format!("{}::agent", happ_id)));
+        assert!(!policy.is_protected(&happ_id));
+        assert!(!policy.is_protected("core-app:0_2_1"));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::plan::{Ineligibility, PlannedAction, ReconciliationSnapshot};
+use crate::planner::check_eligibility;
+use crate::retry::is_connection_error;
+use crate::timeout::is_timeout;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+
+/// A machine-readable account of one reconciliation run
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    /// RFC 3339 start and end of the run
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_ms: u128,
+    /// Every happ published for hosting and whether this holoport may host it
+    pub happs: Vec<HappReport>,
+    /// Every action that was attempted, in order
+    pub actions: Vec<ActionOutcome>,
+}
+
+impl RunReport {
+    pub fn new(
+        started_at: DateTime<Utc>,
+        snapshot: &ReconciliationSnapshot,
+        actions: Vec<ActionOutcome>,
+    ) -> Self {
+        let finished_at = Utc::now();
+        RunReport {
+            started_at: started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            duration_ms: (finished_at - started_at)
+                .to_std()
+                .unwrap_or_default()
+                .as_millis(),
+            happs: HappReport::from_snapshot(snapshot),
+            actions,
+        }
+    }
+
+    /// The actions that were attempted and failed
+    pub fn failed_actions(&self) -> impl Iterator<Item = &ActionOutcome> {
+        self.actions.iter().filter(|outcome| outcome.is_failure())
+    }
+
+    /// The failed actions that failed because an operation timed out
+    pub fn timed_out_actions(&self) -> impl Iterator<Item = &ActionOutcome> {
+        self.actions
+            .iter()
+            .filter(|outcome| matches!(outcome.result, ActionResult::TimedOut { .. }))
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.failed_actions().next().is_some()
+    }
+
+    /// Whether any action failed because a connection broke or timed out
+    pub fn has_connection_failures(&self) -> bool {
+        self.actions
+            .iter()
+            .any(|outcome| outcome.is_connection_failure())
+    }
+}
+
+/// The eligibility verdict for one published happ
+#[derive(Debug, Clone, Serialize)]
+pub struct HappReport {
+    pub happ_id: String,
+    pub eligible: bool,
+    /// Every check from `should_be_enabled` that the happ failed
+    pub ineligibility: Vec<Ineligibility>,
+}
+
+impl HappReport {
+    fn from_snapshot(snapshot: &ReconciliationSnapshot) -> Vec<Self> {
+        snapshot
+            .published_happs
+            .iter()
+            .map(|happ| {
+                let happ_id = happ.happ_id.to_string();
+                let eligibility = check_eligibility(
+                    &happ_id,
+                    &snapshot.suspended_happs,
+                    &snapshot.host_credentials,
+                    &snapshot.host_happ_preferences,
+                    &snapshot.kyc_price_limits,
+                    &snapshot.published_happ_details,
+                );
+                HappReport {
+                    happ_id,
+                    eligible: eligibility.is_eligible(),
+                    ineligibility: eligibility.reasons().to_vec(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// What happened when a planned action was applied
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionOutcome {
+    #[serde(flatten)]
+    pub action: PlannedAction,
+    pub duration_ms: u128,
+    #[serde(flatten)]
+    pub result: ActionResult,
+}
+
+impl ActionOutcome {
+    pub fn new(action: &PlannedAction, duration: Duration, result: ActionResult) -> Self {
+        ActionOutcome {
+            action: action.clone(),
+            duration_ms: duration.as_millis(),
+            result,
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self.result,
+            ActionResult::Failed { .. } | ActionResult::TimedOut { .. }
+        )
+    }
+
+    /// Whether the action failed because a connection broke or timed out, so that it should be reopened
+    pub fn is_connection_failure(&self) -> bool {
+        matches!(
+            self.result,
+            ActionResult::Failed {
+                connection_lost: true,
+                ..
+            } | ActionResult::TimedOut { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ActionResult {
+    Succeeded,
+    Failed {
+        error: String,
+        /// Whether a network failure caused the error
+        #[serde(skip)]
+        connection_lost: bool,
+    },
+    /// Failed because the conductor, hha or an http api did not answer in time, or the run deadline passed
+    TimedOut {
+        error: String,
+    },
+}
+
+impl ActionResult {
+    /// `Failed`, or `TimedOut` if `error` was caused by a timeout
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        let error_message = format!("{:#}", error);
+        if is_timeout(error) {
+            ActionResult::TimedOut {
+                error: error_message,
+            }
+        } else {
+            ActionResult::Failed {
+                error: error_message,
+                connection_lost: is_connection_error(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::HappAction;
+    use crate::timeout::TimedOut;
+    use anyhow::anyhow;
+    use serde_json::json;
+
+    #[test]
+    fn reports_serialise_each_outcome_with_its_status() {
+        let disable = PlannedAction::new("happ::agent", HappAction::Disable, "paused");
+        let holo_disable = PlannedAction::new("happ", HappAction::HoloDisable, "ineligible");
+        let uninstall = PlannedAction::new("other::agent", HappAction::Uninstall, "unpublished");
+        let report = RunReport {
+            started_at: "2024-01-01T00:00:00+00:00".to_string(),
+            finished_at: "2024-01-01T00:00:01+00:00".to_string(),
+            duration_ms: 1000,
+            happs: vec![HappReport {
+                happ_id: "happ".to_string(),
+                eligible: false,
+                ineligibility: vec![Ineligibility::HostDisabled],
+            }],
+            actions: vec![
+                ActionOutcome::new(&disable, Duration::from_millis(5), ActionResult::Succeeded),
+                ActionOutcome::new(
+                    &holo_disable,
+                    Duration::from_millis(6),
+                    ActionResult::from_error(&anyhow!("zome call failed")),
+                ),
+                ActionOutcome::new(
+                    &uninstall,
+                    Duration::from_millis(7),
+                    ActionResult::from_error(
+                        &TimedOut {
+                            operation: "uninstall_app".to_string(),
+                            after: Duration::from_secs(1),
+                        }
+                        .into(),
+                    ),
+                ),
+            ],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&report).expect("report serialises"),
+            json!({
+                "started_at": "2024-01-01T00:00:00+00:00",
+                "finished_at": "2024-01-01T00:00:01+00:00",
+                "duration_ms": 1000,
+                "happs": [{
+                    "happ_id": "happ",
+                    "eligible": false,
+                    "ineligibility": [{ "check": "host_disabled" }],
+                }],
+                "actions": [
+                    {
+                        "installed_app_id": "happ::agent",
+                        "action": { "type": "disable" },
+                        "reason": "paused",
+                        "duration_ms": 5,
+                        "status": "succeeded",
+                    },
+                    {
+                        "installed_app_id": "happ",
+                        "action": { "type": "holo_disable" },
+                        "reason": "ineligible",
+                        "duration_ms": 6,
+                        "status": "failed",
+                        "error": "zome call failed",
+                    },
+                    {
+                        "installed_app_id": "other::agent",
+                        "action": { "type": "uninstall" },
+                        "reason": "unpublished",
+                        "duration_ms": 7,
+                        "status": "timed_out",
+                        "error": "uninstall_app timed out after 1s",
+                    },
+                ],
+            })
+        );
+        assert_eq!(report.failed_actions().count(), 2);
+        assert_eq!(report.timed_out_actions().count(), 1);
+        assert!(report.has_connection_failures());
+    }
+}
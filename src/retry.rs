@@ -0,0 +1,686 @@
+use crate::backend::{AppInstall, ConductorAdmin, HostingRegistry};
+use crate::timeout::{is_timeout, with_timeout};
+use crate::types::{
+    happ::{HappPreferences, PublishedHappPreferences},
+    HappBundle,
+};
+use again::RetryPolicy;
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::future::LocalBoxFuture;
+use holochain_types::dna::ActionHashB64;
+use holochain_types::prelude::{AgentPubKey, Signature};
+use hpos_hc_connect::holofuel_types::PendingTransaction;
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+/// Error messages from the conductor and core app that mean the connection broke, so the call may succeed if made again.
+/// Timeouts are classified by their error type instead, see `is_timeout`.
+const TRANSIENT_ERROR_MESSAGES: &[&str] = &[
+    "connection refused",
+    "connection reset",
+    "connection closed",
+    "websocket closed",
+    "broken pipe",
+];
+
+/// How calls to the conductor, hha, hbs and hpos-api are retried
+#[derive(Debug, Clone)]
+pub struct RetrySettings {
+    /// Retries after the first attempt, 0 disables retrying
+    pub max_retries: usize,
+    /// Delay before the first retry, doubled for every further retry
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Randomize delays, so that holoports do not all retry at the same moment
+    pub jitter: bool,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy::exponential(self.base_delay)
+            .with_max_retries(self.max_retries)
+            .with_max_delay(self.max_delay)
+            .with_jitter(self.jitter)
+    }
+
+    /// Calls `task` until it succeeds, fails with an error that is not retryable, or runs out of retries
+    pub async fn retry<T, F, Fut>(&self, what: &str, task: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        self.policy()
+            .retry_if(task, |e: &anyhow::Error| should_retry(what, e))
+            .await
+    }
+
+    /// Like `retry`, but gives up on each attempt of `task` that takes longer than `timeout`
+    pub async fn retry_with_timeout<T, F, Fut>(
+        &self,
+        what: &str,
+        timeout: Duration,
+        mut task: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        self.retry(what, || with_timeout(what, timeout, task()))
+            .await
+    }
+
+    /// Like `retry`, for calls that change something (eg: installing an app).
+    /// An attempt that timed out may still have been applied, so only failures that show it was not are retried.
+    pub async fn retry_change<T, F, Fut>(&self, what: &str, task: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        self.policy()
+            .retry_if(task, |e: &anyhow::Error| {
+                !is_timeout(e) && should_retry(what, e)
+            })
+            .await
+    }
+}
+
+/// An error status returned by an http api, retryable if the api may succeed later (5xx and 429)
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Whether `error` is transient, ie: a network failure, a timeout or a server error
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    is_connection_error(error)
+        || error.chain().any(|cause| {
+            if let Some(e) = cause.downcast_ref::<HttpStatusError>() {
+                return e.status >= 500 || e.status == 429;
+            }
+            cause
+                .downcast_ref::<reqwest::Error>()
+                .and_then(|e| e.status())
+                .is_some_and(|status| status.is_server_error())
+        })
+}
+
+/// Whether `error` was caused by a network failure or a timeout, after which a connection may need reopening
+pub fn is_connection_error(error: &anyhow::Error) -> bool {
+    if is_timeout(error) {
+        return true;
+    }
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_connect();
+        }
+        if let Some(e) = cause.downcast_ref::<isahc::Error>() {
+            return e.is_network();
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::Interrupted
+            );
+        }
+        let message = cause.to_string().to_lowercase();
+        TRANSIENT_ERROR_MESSAGES
+            .iter()
+            .any(|transient| message.contains(transient))
+    })
+}
+
+fn should_retry(what: &str, error: &anyhow::Error) -> bool {
+    let retryable = is_retryable(error);
+    if retryable {
+        warn!("{} failed, retrying: {:#}", what, error);
+    }
+    retryable
+}
+
+/// Times out and retries the transient failures of every call made to the wrapped conductor or hha.
+/// Calls that change something are not retried after timing out, as they may have been applied.
+/// A call that fails with a connection error marks the connection as broken, and it is reopened before the next call.
+/// Clones share the wrapped connection, so that it can be handed to the installer as well.
+pub struct Retrying<T> {
+    connection: Arc<Mutex<Connection<T>>>,
+    /// Opens a new connection to replace a broken one. Without it, calls are retried on the same connection.
+    reconnect: Option<Reconnect<T>>,
+    retry: RetrySettings,
+    /// How long each attempt of a call may take
+    timeout: Duration,
+}
+
+/// Opens a new connection for a `Retrying`
+pub type Reconnect<T> = Arc<dyn Fn() -> LocalBoxFuture<'static, Result<T>>>;
+
+/// A connection wrapped by `Retrying`, and whether the last call on it failed with a connection error
+struct Connection<T> {
+    inner: T,
+    broken: bool,
+}
+
+impl<T> Connection<T> {
+    /// Marks the connection as broken if `result` is a connection error
+    fn checked<R>(&mut self, result: Result<R>) -> Result<R> {
+        if let Err(e) = &result {
+            if is_connection_error(e) {
+                self.broken = true;
+            }
+        }
+        result
+    }
+}
+
+impl<T> Retrying<T> {
+    pub fn new(inner: T, retry: RetrySettings, timeout: Duration) -> Self {
+        Retrying {
+            connection: Arc::new(Mutex::new(Connection {
+                inner,
+                broken: false,
+            })),
+            reconnect: None,
+            retry,
+            timeout,
+        }
+    }
+
+    pub fn with_reconnect(
+        self,
+        reconnect: impl Fn() -> LocalBoxFuture<'static, Result<T>> + 'static,
+    ) -> Self {
+        Self {
+            reconnect: Some(Arc::new(reconnect)),
+            ..self
+        }
+    }
+
+    /// Locks the connection for a call, first reopening it if the last call broke it
+    async fn lock(&self) -> Result<MutexGuard<'_, Connection<T>>> {
+        let mut connection = self.connection.lock().await;
+        if connection.broken {
+            if let Some(reconnect) = &self.reconnect {
+                warn!("Reopening a connection after a connection error");
+                connection.inner = with_timeout("reconnecting", self.timeout, reconnect()).await?;
+            }
+            connection.broken = false;
+        }
+        Ok(connection)
+    }
+}
+
+impl<T> Clone for Retrying<T> {
+    fn clone(&self) -> Self {
+        Retrying {
+            connection: self.connection.clone(),
+            reconnect: self.reconnect.clone(),
+            retry: self.retry.clone(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: ConductorAdmin> ConductorAdmin for Retrying<T> {
+    async fn list_enabled_app_ids(&mut self) -> Result<Vec<String>> {
+        let this = &*self;
+        self.retry
+            .retry("list_apps", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "list_apps",
+                    this.timeout,
+                    connection.inner.list_enabled_app_ids(),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn list_disabled_app_ids(&mut self) -> Result<Vec<String>> {
+        let this = &*self;
+        self.retry
+            .retry("list_apps", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "list_apps",
+                    this.timeout,
+                    connection.inner.list_disabled_app_ids(),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn disable_app(&mut self, installed_app_id: &str) -> Result<()> {
+        let this = &*self;
+        self.retry
+            .retry_change("disable_app", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "disable_app",
+                    this.timeout,
+                    connection.inner.disable_app(installed_app_id),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn uninstall_app(&mut self, installed_app_id: &str) -> Result<()> {
+        let this = &*self;
+        self.retry
+            .retry_change("uninstall_app", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "uninstall_app",
+                    this.timeout,
+                    connection.inner.uninstall_app(installed_app_id),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn install_app(&mut self, app: AppInstall) -> Result<()> {
+        let this = &*self;
+        let app = &app;
+        self.retry
+            .retry_change("install_app", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "install_app",
+                    this.timeout,
+                    connection.inner.install_app(app.clone()),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn enable_app(&mut self, installed_app_id: &str) -> Result<()> {
+        let this = &*self;
+        self.retry
+            .retry_change("enable_app", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "enable_app",
+                    this.timeout,
+                    connection.inner.enable_app(installed_app_id),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: HostingRegistry> HostingRegistry for Retrying<T> {
+    async fn get_published_happs(&mut self) -> Result<Vec<HappBundle>> {
+        let this = &*self;
+        self.retry
+            .retry("get_happs", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "get_happs",
+                    this.timeout,
+                    connection.inner.get_published_happs(),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn get_happ_preferences(
+        &mut self,
+        happ_id: &ActionHashB64,
+    ) -> Result<PublishedHappPreferences> {
+        let this = &*self;
+        self.retry
+            .retry("get_happ_preferences", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "get_happ_preferences",
+                    this.timeout,
+                    connection.inner.get_happ_preferences(happ_id),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn get_publisher_jurisdiction(
+        &mut self,
+        publisher: &AgentPubKey,
+    ) -> Result<Option<String>> {
+        let this = &*self;
+        self.retry
+            .retry("get_publisher_jurisdiction", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "get_publisher_jurisdiction",
+                    this.timeout,
+                    connection.inner.get_publisher_jurisdiction(publisher),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn get_host_preferences(&mut self) -> Result<HappPreferences> {
+        let this = &*self;
+        self.retry
+            .retry("get_host_preferences", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "get_host_preferences",
+                    this.timeout,
+                    connection.inner.get_host_preferences(),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn get_pending_transactions(&mut self) -> Result<PendingTransaction> {
+        let this = &*self;
+        self.retry
+            .retry("get_pending_transactions", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "get_pending_transactions",
+                    this.timeout,
+                    connection.inner.get_pending_transactions(),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn holo_disable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()> {
+        let this = &*self;
+        self.retry
+            .retry_change("holo_disable_happ", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "holo_disable_happ",
+                    this.timeout,
+                    connection.inner.holo_disable_happ(happ_id),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn holo_enable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()> {
+        let this = &*self;
+        self.retry
+            .retry_change("holo_enable_happ", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "holo_enable_happ",
+                    this.timeout,
+                    connection.inner.holo_enable_happ(happ_id),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn get_host_agent_pubkey(&mut self) -> Result<AgentPubKey> {
+        let this = &*self;
+        self.retry
+            .retry("get_host_agent_pubkey", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "get_host_agent_pubkey",
+                    this.timeout,
+                    connection.inner.get_host_agent_pubkey(),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+
+    async fn sign_raw(&mut self, payload: Vec<u8>) -> Result<Signature> {
+        let this = &*self;
+        let payload = &payload;
+        self.retry
+            .retry("sign_raw", move || async move {
+                let mut connection = this.lock().await?;
+                let result = with_timeout(
+                    "sign_raw",
+                    this.timeout,
+                    connection.inner.sign_raw(payload.clone()),
+                )
+                .await;
+                connection.checked(result)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeout::TimedOut;
+    use anyhow::anyhow;
+    use futures::FutureExt;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Instant;
+
+    fn timed_out() -> anyhow::Error {
+        TimedOut {
+            operation: "list_apps".to_string(),
+            after: Duration::from_secs(1),
+        }
+        .into()
+    }
+
+    fn io_error(kind: ErrorKind) -> anyhow::Error {
+        std::io::Error::from(kind).into()
+    }
+
+    fn status(status: u16) -> anyhow::Error {
+        HttpStatusError {
+            status,
+            message: String::new(),
+        }
+        .into()
+    }
+
+    fn fast_retry(max_retries: usize) -> RetrySettings {
+        RetrySettings {
+            max_retries,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+        }
+    }
+
+    #[test]
+    fn errors_are_classified_by_their_type() {
+        for error in [
+            timed_out(),
+            timed_out().context("failed to list apps"),
+            io_error(ErrorKind::TimedOut),
+            io_error(ErrorKind::ConnectionReset),
+            anyhow!("Websocket closed: protocol error"),
+        ] {
+            assert!(is_connection_error(&error), "{:#}", error);
+            assert!(is_retryable(&error), "{:#}", error);
+        }
+        for error in [status(503), status(429)] {
+            assert!(!is_connection_error(&error), "{:#}", error);
+            assert!(is_retryable(&error), "{:#}", error);
+        }
+        // Only timeouts are timeouts, whatever a message says
+        for error in [
+            status(404),
+            anyhow!("zome call failed"),
+            anyhow!("invoice timed out before it was paid"),
+        ] {
+            assert!(!is_connection_error(&error), "{:#}", error);
+            assert!(!is_retryable(&error), "{:#}", error);
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn transient_failures_are_retried_with_exponential_backoff() {
+        let attempts = Cell::new(0);
+        let started = Instant::now();
+        let result: Result<()> = fast_retry(2)
+            .retry("list_apps", || {
+                attempts.set(attempts.get() + 1);
+                async { Err(io_error(ErrorKind::ConnectionRefused)) }
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+        // 10ms before the first retry, doubled before the second
+        assert!(started.elapsed() >= Duration::from_millis(30));
+
+        attempts.set(0);
+        let result: Result<()> = fast_retry(2)
+            .retry("list_apps", || {
+                attempts.set(attempts.get() + 1);
+                async { Err(status(404)) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn changes_are_not_retried_after_timing_out() {
+        let attempts = Cell::new(0);
+        let result: Result<()> = fast_retry(2)
+            .retry_change("install_app", || {
+                attempts.set(attempts.get() + 1);
+                async { Err(timed_out()) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+
+        attempts.set(0);
+        let result: Result<()> = fast_retry(2)
+            .retry_change("install_app", || {
+                attempts.set(attempts.get() + 1);
+                async { Err(io_error(ErrorKind::ConnectionRefused)) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    /// A connection to the conductor whose calls fail with a reset connection once it is `broken`
+    struct FakeConnection {
+        id: usize,
+        broken: bool,
+    }
+
+    #[async_trait(?Send)]
+    impl ConductorAdmin for FakeConnection {
+        async fn list_enabled_app_ids(&mut self) -> Result<Vec<String>> {
+            if self.broken {
+                return Err(io_error(ErrorKind::ConnectionReset));
+            }
+            Ok(vec![format!("app-on-connection-{}", self.id)])
+        }
+
+        async fn list_disabled_app_ids(&mut self) -> Result<Vec<String>> {
+            self.list_enabled_app_ids().await
+        }
+
+        async fn disable_app(&mut self, _installed_app_id: &str) -> Result<()> {
+            self.list_enabled_app_ids().await.map(|_| ())
+        }
+
+        async fn uninstall_app(&mut self, _installed_app_id: &str) -> Result<()> {
+            self.list_enabled_app_ids().await.map(|_| ())
+        }
+
+        async fn install_app(&mut self, _app: AppInstall) -> Result<()> {
+            self.list_enabled_app_ids().await.map(|_| ())
+        }
+
+        async fn enable_app(&mut self, _installed_app_id: &str) -> Result<()> {
+            self.list_enabled_app_ids().await.map(|_| ())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn calls_are_retried_on_a_new_connection_after_a_connection_error() {
+        let opened = Rc::new(Cell::new(0));
+        let mut conductor = Retrying::new(
+            FakeConnection {
+                id: 0,
+                broken: true,
+            },
+            fast_retry(2),
+            Duration::from_secs(1),
+        )
+        .with_reconnect({
+            let opened = opened.clone();
+            move || {
+                opened.set(opened.get() + 1);
+                let id = opened.get();
+                async move { Ok(FakeConnection { id, broken: false }) }.boxed_local()
+            }
+        });
+
+        assert_eq!(
+            conductor.list_enabled_app_ids().await.unwrap(),
+            vec!["app-on-connection-1"]
+        );
+        // The new connection is kept for the calls after
+        conductor.disable_app("app").await.unwrap();
+        assert_eq!(opened.get(), 1);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::bundle_cache::{DEFAULT_BUNDLE_CACHE_DIR, DEFAULT_BUNDLE_CACHE_SIZE_MIB};
+use crate::executor::DEFAULT_MAX_CONCURRENT_INSTALLS;
+use crate::happ_list::HappList;
+use crate::protection::ProtectionPolicy;
+use crate::retry::RetrySettings;
+use crate::state::DEFAULT_STATE_DIR;
+use crate::suspension::SuspensionPolicy;
+use crate::timeout::TimeoutSettings;
+use crate::types::happ::KycPriceLimits;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use url::Url;
+
+pub const DEFAULT_HPOS_API_URL: &str = "http://localhost";
+
+/// Installer settings that are not part of hpos' holochain `Config`
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Directory holding the installer's state file
+    pub state_dir: PathBuf,
+    /// Base url of hbs, overriding the `HBS_URL` env var
+    pub hbs_url: Option<Url>,
+    /// hpos config file the host's email and holoport id are read from, overriding the `HPOS_CONFIG_PATH` env var
+    pub hpos_config_path: Option<PathBuf>,
+    /// Joining-code service to request membrane proofs from. Hosted happs are installed with read-only proofs if unset.
+    pub joining_code_url: Option<Url>,
+    /// Directory in which downloaded happ bundles are cached
+    pub bundle_cache_dir: PathBuf,
+    /// Size in MiB past which the least recently used cached bundles are evicted
+    pub bundle_cache_size_mib: u64,
+    /// Hex encoded sha256 each happ's bundle must have, keyed by happ id
+    pub pinned_bundle_hashes: HashMap<String, String>,
+    /// How many hosted happs are installed at the same time
+    pub max_concurrent_installs: usize,
+    pub hpos_api: HposApiEndpoint,
+    pub install_backend: InstallBackend,
+    /// Servicelogger bundle installed alongside each hosted happ when installing through the conductor
+    pub servicelogger_bundle_url: Option<Url>,
+    pub retry: RetrySettings,
+    pub timeouts: TimeoutSettings,
+    /// Core and self-hosted happs to install and protect from removal
+    pub happ_list: HappList,
+    /// Which enabled apps a run never disables or uninstalls
+    pub protection: ProtectionPolicy,
+    /// Where the host's measured uptime is read from
+    pub uptime_source: UptimeSource,
+    /// How long hosting invoices may be overdue before their happ is disabled, and then uninstalled
+    pub suspension: SuspensionPolicy,
+    /// The most a happ may pay a host of each kyc level per unit of any resource
+    pub kyc_price_limits: KycPriceLimits,
+}
+
+/// Where the host's measured uptime, which happs' minimum uptime is checked against, is read from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UptimeSource {
+    /// The uptime hbs reports with the host's credentials
+    #[default]
+    Hbs,
+    /// A local file holding the uptime as a fraction between 0 and 1 (eg: `0.97`)
+    File(PathBuf),
+}
+
+impl UptimeSource {
+    /// Reads the host's uptime, given the uptime hbs reported
+    pub fn read_host_uptime(&self, hbs_uptime: Option<f32>) -> Result<Option<f32>> {
+        match self {
+            UptimeSource::Hbs => Ok(hbs_uptime),
+            UptimeSource::File(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read uptime file {}", path.display()))?;
+                let uptime: f32 = contents
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("failed to parse uptime file {}", path.display()))?;
+                if !(0.0..=1.0).contains(&uptime) {
+                    return Err(anyhow!(
+                        "Uptime {} in {} is not between 0 and 1",
+                        uptime,
+                        path.display()
+                    ));
+                }
+                Ok(Some(uptime))
+            }
+        }
+    }
+}
+
+impl FromStr for UptimeSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hbs" => Ok(UptimeSource::Hbs),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(UptimeSource::File(PathBuf::from(path))),
+                _ => Err(anyhow!("Unsupported uptime source {}", s)),
+            },
+        }
+    }
+}
+
+/// What hosted happs are installed through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InstallBackend {
+    /// hpos-api's `/api/v2/apps/hosted/install` endpoint
+    #[default]
+    HposApi,
+    /// The conductor's admin interface and the core app, step by step
+    Conductor,
+}
+
+impl FromStr for InstallBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hpos-api" => Ok(InstallBackend::HposApi),
+            "conductor" => Ok(InstallBackend::Conductor),
+            _ => Err(anyhow!("Unsupported install backend {}", s)),
+        }
+    }
+}
+
+/// Where hpos-api listens, and how to authenticate with it
+#[derive(Debug, Clone)]
+pub struct HposApiEndpoint {
+    /// Base url that api paths (eg: `api/v2/apps/hosted/install`) are joined to, ending with a `/`
+    pub base_url: Url,
+    /// Unix socket to connect through instead of the base url's host and port
+    pub socket_path: Option<PathBuf>,
+    /// Value of the `Authorization` header sent with every request (eg: `Bearer <token>`)
+    pub authorization: Option<String>,
+}
+
+impl HposApiEndpoint {
+    /// Adds the trailing `/` `base_url` may be missing, as joining a path to a url without one replaces its last segment
+    /// (eg: `http://localhost/hpos` would send installs to `http://localhost/api/...`)
+    pub fn new(
+        mut base_url: Url,
+        socket_path: Option<PathBuf>,
+        authorization: Option<String>,
+    ) -> Self {
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        HposApiEndpoint {
+            base_url,
+            socket_path,
+            authorization,
+        }
+    }
+}
+
+impl Default for HposApiEndpoint {
+    fn default() -> Self {
+        HposApiEndpoint {
+            base_url: Url::parse(DEFAULT_HPOS_API_URL).expect("default hpos-api url is valid"),
+            socket_path: None,
+            authorization: None,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            state_dir: PathBuf::from(DEFAULT_STATE_DIR),
+            hbs_url: None,
+            hpos_config_path: None,
+            joining_code_url: None,
+            bundle_cache_dir: PathBuf::from(DEFAULT_BUNDLE_CACHE_DIR),
+            bundle_cache_size_mib: DEFAULT_BUNDLE_CACHE_SIZE_MIB,
+            pinned_bundle_hashes: HashMap::new(),
+            max_concurrent_installs: DEFAULT_MAX_CONCURRENT_INSTALLS,
+            hpos_api: HposApiEndpoint::default(),
+            install_backend: InstallBackend::default(),
+            servicelogger_bundle_url: None,
+            retry: RetrySettings::default(),
+            timeouts: TimeoutSettings::default(),
+            happ_list: HappList::default(),
+            protection: ProtectionPolicy::default(),
+            uptime_source: UptimeSource::default(),
+            suspension: SuspensionPolicy::default(),
+            kyc_price_limits: KycPriceLimits::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install_url(base_url: &str) -> String {
+        HposApiEndpoint::new(Url::parse(base_url).unwrap(), None, None)
+            .base_url
+            .join("api/v2/apps/hosted/install")
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn api_paths_are_joined_below_the_whole_base_url() {
+        assert_eq!(
+            install_url("http://localhost/hpos"),
+            "http://localhost/hpos/api/v2/apps/hosted/install"
+        );
+        assert_eq!(
+            install_url("http://localhost/hpos/"),
+            "http://localhost/hpos/api/v2/apps/hosted/install"
+        );
+        assert_eq!(
+            install_url("http://localhost"),
+            "http://localhost/api/v2/apps/hosted/install"
+        );
+    }
+}
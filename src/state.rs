@@ -0,0 +1,248 @@
+use crate::plan::{ActionTarget, DecisionInputs, HappAction, PlannedAction};
+use crate::suspension::SuspensionStage;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+use tracing::{debug, trace};
+
+pub const DEFAULT_STATE_DIR: &str = "/var/lib/holo-auto-installer";
+const STATE_FILE_NAME: &str = "state.json";
+
+/// What the installer last did to an app, and why
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppState {
+    pub last_action: String,
+    pub reason: String,
+    /// RFC 3339 time at which the action was applied
+    pub timestamp: String,
+    pub inputs: DecisionInputs,
+}
+
+/// The installer's memory between runs. Actions in the conductor and in hha are kept apart,
+/// as the anonymous instance of a hosted happ has the same id as the happ.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InstallerState {
+    /// What was last done to each app in the conductor, keyed by `installed_app_id`
+    pub apps: BTreeMap<String, AppState>,
+    /// What was last done to each happ in hha, keyed by happ id
+    #[serde(default)]
+    pub happs: BTreeMap<String, AppState>,
+}
+
+/// Reads and writes `InstallerState` as JSON in the state dir.
+/// Another process (eg: the `uninstall` subcommand while the daemon runs) may write the same state file,
+/// so saving merges the actions recorded since the last save into the file as it is on disk, under a file lock.
+#[derive(Debug, Clone, Default)]
+pub struct StateStore {
+    /// `None` for a store that is never persisted (eg: in tests)
+    path: Option<PathBuf>,
+    state: InstallerState,
+    /// The actions recorded since the state was last saved
+    unsaved: InstallerState,
+}
+
+impl StateStore {
+    /// Loads the state from `state_dir`, starting empty if no state has been written there yet
+    pub fn load(state_dir: &Path) -> Result<Self> {
+        let path = state_dir.join(STATE_FILE_NAME);
+        Ok(StateStore {
+            state: read_state(&path)?,
+            path: Some(path),
+            unsaved: InstallerState::default(),
+        })
+    }
+
+    /// A store that is kept in memory only
+    pub fn in_memory() -> Self {
+        StateStore::default()
+    }
+
+    pub fn state(&self) -> &InstallerState {
+        &self.state
+    }
+
+    /// Reads the state file again, picking up what other processes saved since, and keeps the unsaved actions on top
+    pub fn reload(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut state = read_state(path)?;
+        state.merge(self.unsaved.clone());
+        self.state = state;
+        Ok(())
+    }
+
+    /// Records that `action` was just applied
+    pub fn record(&mut self, action: &PlannedAction, inputs: DecisionInputs) {
+        trace!("Recording {} of {}", action.action, action.installed_app_id);
+        let app = AppState {
+            last_action: action.action.to_string(),
+            reason: action.reason.clone(),
+            timestamp: Utc::now().to_rfc3339(),
+            inputs,
+        };
+        for state in [&mut self.state, &mut self.unsaved] {
+            let records = match action.action.target() {
+                ActionTarget::Conductor => &mut state.apps,
+                ActionTarget::Hha => &mut state.happs,
+            };
+            records.insert(action.installed_app_id.clone(), app.clone());
+        }
+    }
+
+    /// Writes the actions recorded since the last save into the state file, replacing it atomically.
+    /// The file is read again under a lock first, so that what other processes saved in the meantime is kept.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create state dir {}", dir.display()))?;
+
+        let lock_path = path.with_extension("lock");
+        let lock = File::create(&lock_path)
+            .with_context(|| format!("failed to open state lock {}", lock_path.display()))?;
+        lock.lock()
+            .with_context(|| format!("failed to lock {}", lock_path.display()))?;
+
+        let mut state = read_state(path)?;
+        state.merge(std::mem::take(&mut self.unsaved));
+
+        let mut file = NamedTempFile::new_in(dir)?;
+        file.write_all(serde_json::to_string_pretty(&state)?.as_bytes())?;
+        file.persist(path)
+            .with_context(|| format!("failed to write state file {}", path.display()))?;
+        debug!("Saved installer state to {}", path.display());
+        self.state = state;
+        // The lock is released when `lock` is dropped
+        Ok(())
+    }
+}
+
+/// Reads the state file at `path`, or an empty state if there is none yet
+fn read_state(path: &Path) -> Result<InstallerState> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse state file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!("No state file at {}, starting empty", path.display());
+            Ok(InstallerState::default())
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to read state file {}", path.display())),
+    }
+}
+
+impl InstallerState {
+    /// Overwrites the records of every app and happ in `newer`
+    fn merge(&mut self, newer: InstallerState) {
+        self.apps.extend(newer.apps);
+        self.happs.extend(newer.happs);
+    }
+
+    /// The apps last disabled at the disable stage of a suspension
+    pub fn suspension_disabled_app_ids(&self) -> Vec<String> {
+        self.apps
+            .iter()
+            .filter(|(_, app)| {
+                app.last_action == HappAction::Disable.to_string()
+                    && app.inputs.suspension_stage == Some(SuspensionStage::Disable)
+            })
+            .map(|(installed_app_id, _)| installed_app_id.clone())
+            .collect()
+    }
+
+    /// The apps, and happs, last acted on because the host asked for them to be uninstalled
+    pub fn removal_requested_app_ids(&self) -> Vec<String> {
+        self.apps
+            .iter()
+            .chain(self.happs.iter())
+            .filter(|(_, app)| app.inputs.is_removal_requested)
+            .map(|(id, _)| id.clone())
+            .unique()
+            .collect()
+    }
+}
+
+impl fmt::Display for InstallerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.apps.is_empty() && self.happs.is_empty() {
+            return writeln!(f, "The installer has not changed any apps yet");
+        }
+        for (installed_app_id, app) in self.apps.iter().chain(self.happs.iter()) {
+            writeln!(
+                f,
+                "{}  {:<12} {} ({})",
+                app.timestamp, app.last_action, installed_app_id, app.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn removal_requested() -> DecisionInputs {
+        DecisionInputs {
+            is_removal_requested: true,
+            ..DecisionInputs::default()
+        }
+    }
+
+    #[test]
+    fn removal_requests_saved_between_two_cycles_are_kept() {
+        let state_dir = tempfile::tempdir().expect("temp dir");
+        let mut daemon = StateStore::load(state_dir.path()).expect("state loads");
+
+        // First cycle
+        daemon.record(
+            &PlannedAction::new("app-1", HappAction::Uninstall, "ineligible"),
+            DecisionInputs::default(),
+        );
+        daemon.save().expect("state saves");
+
+        // The `uninstall` subcommand runs between the cycles
+        let mut uninstall = StateStore::load(state_dir.path()).expect("state loads");
+        uninstall.record(
+            &PlannedAction::new("happ-2", HappAction::HoloDisable, "requested"),
+            removal_requested(),
+        );
+        uninstall.save().expect("state saves");
+
+        // Second cycle
+        daemon.reload().expect("state reloads");
+        assert_eq!(daemon.state().removal_requested_app_ids(), vec!["happ-2"]);
+        daemon.record(
+            &PlannedAction::new("app-3", HappAction::Disable, "paused"),
+            DecisionInputs::default(),
+        );
+
+        // Another removal request comes in before the second cycle saves
+        uninstall.record(
+            &PlannedAction::new("happ-4", HappAction::HoloDisable, "requested"),
+            removal_requested(),
+        );
+        uninstall.save().expect("state saves");
+        daemon.save().expect("state saves");
+
+        let saved = StateStore::load(state_dir.path()).expect("state loads");
+        assert_eq!(
+            saved.state().apps.keys().collect::<Vec<_>>(),
+            vec!["app-1", "app-3"]
+        );
+        assert_eq!(
+            saved.state().removal_requested_app_ids(),
+            vec!["happ-2", "happ-4"]
+        );
+        assert_eq!(saved.state(), daemon.state());
+    }
+}
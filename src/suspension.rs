@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// How far the suspension of a happ with an overdue hosting invoice has gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuspensionStage {
+    /// Within the grace period: the happ stays enabled and the host is only warned
+    Warn,
+    /// Every instance of the happ is holochain-disabled, and is enabled again once the invoice is paid
+    Disable,
+    /// Identified instances are uninstalled and the happ is holo-disabled for this holoport
+    Uninstall,
+}
+
+impl fmt::Display for SuspensionStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SuspensionStage::Warn => "warn",
+            SuspensionStage::Disable => "disable",
+            SuspensionStage::Uninstall => "uninstall",
+        })
+    }
+}
+
+/// The most overdue hosting invoice of a happ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverdueInvoice {
+    /// Id of the invoice
+    pub invoice: String,
+    /// Time since the invoice's expiration date
+    pub overdue_for: Duration,
+    pub stage: SuspensionStage,
+}
+
+impl OverdueInvoice {
+    /// Whether the happ is past the grace period, and so may not stay enabled
+    pub fn is_suspended(&self) -> bool {
+        self.stage >= SuspensionStage::Disable
+    }
+
+    /// Whole days since the invoice's expiration date
+    pub fn overdue_days(&self) -> u64 {
+        self.overdue_for.as_secs() / SECS_PER_DAY
+    }
+}
+
+/// How long a hosting invoice may be overdue before its happ is disabled, and then uninstalled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuspensionPolicy {
+    pub grace_period: Duration,
+    /// Counted from the invoice's expiration date, like the grace period
+    pub uninstall_after: Duration,
+}
+
+impl SuspensionPolicy {
+    /// Fails if the grace period outlasts the uninstall stage, as the disable stage would then be skipped
+    pub fn from_days(grace_days: u64, uninstall_days: u64) -> Result<Self> {
+        if grace_days > uninstall_days {
+            return Err(anyhow!(
+                "Suspension grace period of {} days is longer than the {} days after which happs are uninstalled",
+                grace_days,
+                uninstall_days
+            ));
+        }
+        Ok(SuspensionPolicy {
+            grace_period: Duration::from_secs(grace_days * SECS_PER_DAY),
+            uninstall_after: Duration::from_secs(uninstall_days * SECS_PER_DAY),
+        })
+    }
+
+    pub fn grace_days(&self) -> u64 {
+        self.grace_period.as_secs() / SECS_PER_DAY
+    }
+
+    pub fn stage(&self, overdue_for: Duration) -> SuspensionStage {
+        if overdue_for < self.grace_period {
+            SuspensionStage::Warn
+        } else if overdue_for < self.uninstall_after {
+            SuspensionStage::Disable
+        } else {
+            SuspensionStage::Uninstall
+        }
+    }
+}
+
+/// Happs are uninstalled as soon as an invoice is overdue, as they were before suspensions were staged
+impl Default for SuspensionPolicy {
+    fn default() -> Self {
+        SuspensionPolicy {
+            grace_period: Duration::ZERO,
+            uninstall_after: Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grace_period_may_not_outlast_the_uninstall_stage() {
+        assert!(SuspensionPolicy::from_days(8, 7).is_err());
+        assert!(SuspensionPolicy::from_days(7, 7).is_ok());
+    }
+
+    #[test]
+    fn by_default_happs_are_uninstalled_as_soon_as_an_invoice_is_overdue() {
+        let policy = SuspensionPolicy::default();
+        assert_eq!(policy.stage(Duration::ZERO), SuspensionStage::Uninstall);
+        assert_eq!(
+            policy.stage(Duration::from_secs(SECS_PER_DAY)),
+            SuspensionStage::Uninstall
+        );
+    }
+
+    #[test]
+    fn happs_go_through_each_stage_in_turn() {
+        let policy = SuspensionPolicy::from_days(2, 5).expect("valid policy");
+        let after_days = |days: u64| policy.stage(Duration::from_secs(days * SECS_PER_DAY));
+        assert_eq!(after_days(1), SuspensionStage::Warn);
+        assert_eq!(after_days(2), SuspensionStage::Disable);
+        assert_eq!(after_days(4), SuspensionStage::Disable);
+        assert_eq!(after_days(5), SuspensionStage::Uninstall);
+    }
+}
@@ -0,0 +1,655 @@
+//! In-memory stand-ins for the conductor, hha, hbs and hpos-api,
+//! so that a whole `reconcile` can be exercised offline (eg: from `cargo test`),
+//! and builders for the hha and hbs values they serve.
+//! Built for this crate's unit tests, and for other crates with the `test-support` feature.
+use crate::backend::{AppInstall, ConductorAdmin, HostBackend, HostedInstaller, HostingRegistry};
+use crate::happ_list::HappList;
+use crate::plan::ReconciliationSnapshot;
+use crate::protection::ProtectionPolicy;
+use crate::types::{
+    happ::{HappPreferences, HappPricing, KycPriceLimits, PublishedHappPreferences},
+    hbs::{HostCredentials, KycLevel},
+    HappBundle, PublishedHappDetails,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use holochain_types::dna::ActionHashB64;
+use holochain_types::prelude::{
+    ActionHash, AgentPubKey, AppManifest, AppManifestCurrentBuilder, AppRoleDnaManifest,
+    AppRoleManifest, CellProvisioning, DnaBundle, DnaManifest, DnaModifiersOpt, ResourceBytes,
+    Signature, Timestamp, YamlProperties,
+};
+use holofuel_types::fuel::Fuel;
+use hpos_hc_connect::hha_types::HostSettings;
+use hpos_hc_connect::holofuel_types::PendingTransaction;
+use mr_bundle::{Bundle, Location};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use url::Url;
+
+pub fn fuel(amount: &str) -> Fuel {
+    amount.parse().expect("valid fuel amount")
+}
+
+/// The id of the `n`th fake happ
+pub fn fake_happ_id(n: u8) -> ActionHashB64 {
+    ActionHash::from_raw_36(vec![n; 36]).into()
+}
+
+/// The `n`th fake happ, holo-enabled by the host, neither paused nor running on a core app, and requiring no uptime
+pub fn fake_happ(n: u8) -> HappBundle {
+    HappBundle {
+        happ_id: fake_happ_id(n),
+        bundle_url: format!("https://bundles.holo.host/{}.happ", n),
+        is_paused: false,
+        is_host_disabled: false,
+        special_installed_app_id: None,
+        jurisdictions: vec![],
+        exclude_jurisdictions: true,
+        categories: vec![],
+        host_settings: HostSettings {
+            is_enabled: true,
+            is_host_disabled: false,
+            is_auto_disabled: false,
+        },
+        dna_hashes: vec![],
+        min_uptime: 0.0,
+    }
+}
+
+/// Preferences of a host charging 1 fuel for every resource, with no jurisdiction or category preferences
+pub fn fake_host_preferences() -> HappPreferences {
+    HappPreferences {
+        max_fuel_before_invoice: fuel("1000"),
+        max_time_before_invoice: Duration::from_secs(7 * 24 * 60 * 60),
+        price_compute: fuel("1"),
+        price_storage: fuel("1"),
+        price_bandwidth: fuel("1"),
+        invoice_due_in_days: 7,
+        jurisdiction_prefs: None,
+        categories_prefs: None,
+    }
+}
+
+/// Credentials of a kyc level 2 host in `DE` that is always online
+pub fn fake_host_credentials() -> HostCredentials {
+    HostCredentials {
+        jurisdiction: Some("DE".to_string()),
+        kyc: KycLevel::Level2,
+        uptime: Some(1.0),
+        ..HostCredentials::default()
+    }
+}
+
+/// A snapshot of the `fake_host_credentials` host, with the `fake_host_preferences` and nothing installed,
+/// on which each of `happs` is published by a publisher in `DE` at exactly the host's prices
+pub fn fake_snapshot(happs: Vec<HappBundle>) -> ReconciliationSnapshot {
+    let host_happ_preferences = fake_host_preferences();
+    let published_happ_details = happs
+        .iter()
+        .map(|happ| {
+            (
+                happ.happ_id.to_string(),
+                PublishedHappDetails {
+                    publisher_jurisdiction: Some("DE".to_string()),
+                    happ_jurisdictions: happ.jurisdictions.clone(),
+                    should_exclude_happ_jurisdictions: happ.exclude_jurisdictions,
+                    happ_categories: happ.categories.clone(),
+                    is_disabled_by_host: happ.is_host_disabled,
+                    happ_pricing: HappPricing::matching(&host_happ_preferences),
+                    min_uptime: happ.min_uptime,
+                },
+            )
+        })
+        .collect();
+    ReconciliationSnapshot {
+        published_happs: happs,
+        suspended_happs: HashMap::new(),
+        host_credentials: fake_host_credentials(),
+        host_happ_preferences,
+        kyc_price_limits: KycPriceLimits::default(),
+        published_happ_details,
+        enabled_happ_ids: vec![],
+        disabled_happ_ids: vec![],
+        happ_list: HappList::default(),
+        protection: ProtectionPolicy::default(),
+        suspension_disabled_app_ids: vec![],
+        removal_requested_app_ids: vec![],
+    }
+}
+
+pub fn no_pending_transactions() -> PendingTransaction {
+    PendingTransaction {
+        invoice_pending: vec![],
+        promise_pending: vec![],
+        invoice_declined: vec![],
+        promise_declined: vec![],
+    }
+}
+
+/// The apps a `FakeConductor` holds, by status
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FakeConductorState {
+    pub enabled: Vec<String>,
+    pub disabled: Vec<String>,
+    /// Apps that were uninstalled during the run
+    pub uninstalled: Vec<String>,
+    /// Every change made during the run, in order, eg: `enable_app <installed_app_id>`.
+    /// The installs of a `FakeInstaller` are logged as `install_hosted_happ <happ_id>`.
+    pub changes: Vec<String>,
+}
+
+/// A conductor whose apps live in memory. Clones share the same state,
+/// so a `FakeInstaller` can install into the conductor the executor reads from.
+#[derive(Debug, Default, Clone)]
+pub struct FakeConductor {
+    state: Arc<Mutex<FakeConductorState>>,
+}
+
+impl FakeConductor {
+    pub fn with_enabled_apps<I, S>(installed_app_ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let conductor = FakeConductor::default();
+        conductor.state().enabled = installed_app_ids.into_iter().map(Into::into).collect();
+        conductor
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, FakeConductorState> {
+        self.state.lock().expect("fake conductor state poisoned")
+    }
+}
+
+#[async_trait(?Send)]
+impl ConductorAdmin for FakeConductor {
+    async fn list_enabled_app_ids(&mut self) -> Result<Vec<String>> {
+        Ok(self.state().enabled.clone())
+    }
+
+    async fn list_disabled_app_ids(&mut self) -> Result<Vec<String>> {
+        Ok(self.state().disabled.clone())
+    }
+
+    async fn disable_app(&mut self, installed_app_id: &str) -> Result<()> {
+        let mut state = self.state();
+        if !state.enabled.iter().any(|id| id == installed_app_id) {
+            return Err(anyhow!("App {} is not enabled", installed_app_id));
+        }
+        state.enabled.retain(|id| id != installed_app_id);
+        state.disabled.push(installed_app_id.to_string());
+        state
+            .changes
+            .push(format!("disable_app {}", installed_app_id));
+        Ok(())
+    }
+
+    async fn uninstall_app(&mut self, installed_app_id: &str) -> Result<()> {
+        let mut state = self.state();
+        let was_installed = state
+            .enabled
+            .iter()
+            .chain(state.disabled.iter())
+            .any(|id| id == installed_app_id);
+        if !was_installed {
+            return Err(anyhow!("App {} is not installed", installed_app_id));
+        }
+        state.enabled.retain(|id| id != installed_app_id);
+        state.disabled.retain(|id| id != installed_app_id);
+        state.uninstalled.push(installed_app_id.to_string());
+        state
+            .changes
+            .push(format!("uninstall_app {}", installed_app_id));
+        Ok(())
+    }
+
+    async fn install_app(&mut self, app: AppInstall) -> Result<()> {
+        let mut state = self.state();
+        let installed_app_id = app.installed_app_id;
+        if state
+            .enabled
+            .iter()
+            .chain(state.disabled.iter())
+            .any(|id| *id == installed_app_id)
+        {
+            return Err(anyhow!("App {} is already installed", installed_app_id));
+        }
+        state
+            .changes
+            .push(format!("install_app {}", installed_app_id));
+        state.disabled.push(installed_app_id);
+        Ok(())
+    }
+
+    async fn enable_app(&mut self, installed_app_id: &str) -> Result<()> {
+        let mut state = self.state();
+        if !state.disabled.iter().any(|id| id == installed_app_id) {
+            return Err(anyhow!(
+                "App {} is not installed and disabled",
+                installed_app_id
+            ));
+        }
+        state.disabled.retain(|id| id != installed_app_id);
+        state.enabled.push(installed_app_id.to_string());
+        state
+            .changes
+            .push(format!("enable_app {}", installed_app_id));
+        Ok(())
+    }
+}
+
+/// Stands in for hpos-api: installing a happ enables it and its servicelogger in the shared `FakeConductor`,
+/// or only its servicelogger if the happ runs on a core app
+#[derive(Debug, Default, Clone)]
+pub struct FakeInstaller {
+    conductor: FakeConductor,
+    installed: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeInstaller {
+    pub fn new(conductor: &FakeConductor) -> Self {
+        FakeInstaller {
+            conductor: conductor.clone(),
+            installed: Default::default(),
+        }
+    }
+
+    /// The happ ids installed through this installer, in order
+    pub fn installed(&self) -> Vec<String> {
+        self.installed
+            .lock()
+            .expect("fake installer state poisoned")
+            .clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl HostedInstaller for FakeInstaller {
+    async fn install_hosted_happ(
+        &self,
+        happ_id: &str,
+        _bundle_url: &str,
+        _dna_hashes: &[String],
+        special_installed_app_id: Option<&str>,
+    ) -> Result<()> {
+        let mut installed_app_ids = vec![format!("{}::servicelogger", happ_id)];
+        if special_installed_app_id.is_none() {
+            installed_app_ids.push(happ_id.to_string());
+        }
+        let mut state = self.conductor.state();
+        for installed_app_id in installed_app_ids {
+            state.disabled.retain(|id| *id != installed_app_id);
+            if !state.enabled.contains(&installed_app_id) {
+                state.enabled.push(installed_app_id);
+            }
+        }
+        state
+            .changes
+            .push(format!("install_hosted_happ {}", happ_id));
+        self.installed
+            .lock()
+            .expect("fake installer state poisoned")
+            .push(happ_id.to_string());
+        Ok(())
+    }
+}
+
+/// Stands in for hha, as reached through the core app
+#[derive(Debug, Clone)]
+pub struct FakeHostingRegistry {
+    pub published_happs: Vec<HappBundle>,
+    /// The provider and pricing of each published happ, keyed by happ id
+    pub happ_preferences: HashMap<String, PublishedHappPreferences>,
+    pub publisher_jurisdictions: HashMap<AgentPubKey, Option<String>>,
+    pub host_preferences: HappPreferences,
+    pub pending_transactions: PendingTransaction,
+    /// Happ ids that were holo-disabled during the run
+    pub holo_disabled: Vec<String>,
+    /// Happ ids that were holo-enabled during the run
+    pub holo_enabled: Vec<String>,
+    pub host_agent_pubkey: AgentPubKey,
+    /// Returned as the host agent's signature of any payload
+    pub host_signature: Signature,
+}
+
+impl FakeHostingRegistry {
+    pub fn new(
+        host_preferences: HappPreferences,
+        pending_transactions: PendingTransaction,
+    ) -> Self {
+        FakeHostingRegistry {
+            published_happs: vec![],
+            happ_preferences: HashMap::new(),
+            publisher_jurisdictions: HashMap::new(),
+            host_preferences,
+            pending_transactions,
+            holo_disabled: vec![],
+            holo_enabled: vec![],
+            host_agent_pubkey: AgentPubKey::from_raw_36(vec![0; 36]),
+            host_signature: Signature([1; 64]),
+        }
+    }
+
+    /// Publishes `happ` for hosting by `publisher`, priced to exactly meet the host's terms
+    pub fn publish(&mut self, happ: HappBundle, publisher: AgentPubKey) {
+        let pricing = HappPricing::matching(&self.host_preferences);
+        self.publish_with_pricing(happ, publisher, pricing);
+    }
+
+    /// Publishes `happ` for hosting by `publisher` at `pricing`
+    pub fn publish_with_pricing(
+        &mut self,
+        happ: HappBundle,
+        publisher: AgentPubKey,
+        pricing: HappPricing,
+    ) {
+        self.happ_preferences.insert(
+            happ.happ_id.to_string(),
+            PublishedHappPreferences { publisher, pricing },
+        );
+        self.published_happs.push(happ);
+    }
+
+    fn set_host_enabled(&mut self, happ_id: &ActionHashB64, is_enabled: bool) {
+        for happ in self
+            .published_happs
+            .iter_mut()
+            .filter(|happ| &happ.happ_id == happ_id)
+        {
+            happ.host_settings.is_enabled = is_enabled;
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl HostingRegistry for FakeHostingRegistry {
+    async fn get_published_happs(&mut self) -> Result<Vec<HappBundle>> {
+        Ok(self.published_happs.clone())
+    }
+
+    async fn get_happ_preferences(
+        &mut self,
+        happ_id: &ActionHashB64,
+    ) -> Result<PublishedHappPreferences> {
+        self.happ_preferences
+            .get(&happ_id.to_string())
+            .cloned()
+            .ok_or_else(|| anyhow!("No preferences registered for happ {}", happ_id))
+    }
+
+    async fn get_publisher_jurisdiction(
+        &mut self,
+        publisher: &AgentPubKey,
+    ) -> Result<Option<String>> {
+        Ok(self
+            .publisher_jurisdictions
+            .get(publisher)
+            .cloned()
+            .flatten())
+    }
+
+    async fn get_host_preferences(&mut self) -> Result<HappPreferences> {
+        Ok(self.host_preferences.clone())
+    }
+
+    async fn get_pending_transactions(&mut self) -> Result<PendingTransaction> {
+        Ok(self.pending_transactions.clone())
+    }
+
+    async fn holo_disable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()> {
+        self.set_host_enabled(happ_id, false);
+        self.holo_disabled.push(happ_id.to_string());
+        Ok(())
+    }
+
+    async fn holo_enable_happ(&mut self, happ_id: &ActionHashB64) -> Result<()> {
+        self.set_host_enabled(happ_id, true);
+        self.holo_enabled.push(happ_id.to_string());
+        Ok(())
+    }
+
+    async fn get_host_agent_pubkey(&mut self) -> Result<AgentPubKey> {
+        Ok(self.host_agent_pubkey.clone())
+    }
+
+    async fn sign_raw(&mut self, _payload: Vec<u8>) -> Result<Signature> {
+        Ok(self.host_signature.clone())
+    }
+}
+
+/// Stands in for hbs
+#[derive(Debug, Clone, Default)]
+pub struct FakeHostBackend {
+    pub host_credentials: HostCredentials,
+}
+
+#[async_trait(?Send)]
+impl HostBackend for FakeHostBackend {
+    async fn get_host_credentials(&self) -> Result<HostCredentials> {
+        Ok(self.host_credentials.clone())
+    }
+}
+
+/// A DNA without zomes, encoded as it is bundled in a happ
+pub fn fake_dna_bytes() -> ResourceBytes {
+    let manifest = DnaManifest::current(
+        "test".to_string(),
+        None,
+        None,
+        Timestamp::HOLOCHAIN_EPOCH.into(),
+        vec![],
+        vec![],
+    );
+    let dna = DnaBundle::new(
+        manifest.try_into().expect("valid dna manifest"),
+        vec![],
+        PathBuf::from("."),
+    )
+    .expect("dna bundles");
+    dna.encode().expect("dna encodes").into()
+}
+
+pub fn fake_dna_modifiers(network_seed: Option<&str>) -> DnaModifiersOpt<YamlProperties> {
+    DnaModifiersOpt {
+        network_seed: network_seed.map(str::to_string),
+        ..DnaModifiersOpt::none()
+    }
+}
+
+/// Writes a happ bundle with one role, named `test` and seeded with `network_seed`, to `dir`
+pub async fn write_fake_bundle(dir: &Path, network_seed: Option<&str>) -> PathBuf {
+    let dna_path = PathBuf::from("test.dna");
+    let role = AppRoleManifest {
+        name: "test".to_string(),
+        provisioning: Some(CellProvisioning::Create { deferred: false }),
+        dna: AppRoleDnaManifest {
+            location: Some(Location::Bundled(dna_path.clone())),
+            modifiers: fake_dna_modifiers(network_seed),
+            installed_hash: None,
+            clone_limit: 0,
+        },
+    };
+    let manifest: AppManifest = AppManifestCurrentBuilder::default()
+        .name("test".to_string())
+        .description(None)
+        .roles(vec![role])
+        .build()
+        .expect("valid app manifest")
+        .into();
+    let bundle = Bundle::new(
+        manifest,
+        vec![(dna_path, fake_dna_bytes())],
+        dir.to_path_buf(),
+    )
+    .expect("happ bundles");
+    let path = dir.join("test.happ");
+    bundle
+        .write_to_file(&path)
+        .await
+        .expect("happ bundle is written");
+    path
+}
+
+/// A request received by a `FakeHttpServer`
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    /// Keyed by lowercase header name
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+/// What a `FakeHttpServer` answers a request with
+#[derive(Debug, Clone)]
+pub struct FakeResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl FakeResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        FakeResponse {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Stands in for the http services the installer calls (eg: the joining-code service or the bundle CDN).
+/// Serves HTTP/1.1 on a local port until dropped, answering every request with `respond` and closing the connection.
+pub struct FakeHttpServer {
+    url: Url,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+    task: JoinHandle<()>,
+}
+
+impl FakeHttpServer {
+    pub async fn start(
+        respond: impl Fn(&ReceivedRequest) -> FakeResponse + Send + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("fake http server binds");
+        let address = listener
+            .local_addr()
+            .expect("fake http server has an address");
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let Ok(request) = read_request(&mut stream).await else {
+                    continue;
+                };
+                let response = respond(&request);
+                received.lock().expect("requests lock").push(request);
+                let _ = write_response(&mut stream, response).await;
+            }
+        });
+        FakeHttpServer {
+            url: Url::parse(&format!("http://{}/", address)).expect("valid url"),
+            requests,
+            task,
+        }
+    }
+
+    /// The url of `path` on this server
+    pub fn url(&self, path: &str) -> Url {
+        self.url.join(path).expect("valid path")
+    }
+
+    /// The requests received so far, in order
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.requests.lock().expect("requests lock").clone()
+    }
+}
+
+impl Drop for FakeHttpServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<ReceivedRequest> {
+    let mut buffer = vec![];
+    let mut chunk = [0; 4096];
+    let head_len = loop {
+        if let Some(end) = buffer.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_len]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = buffer[head_len..].to_vec();
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    Ok(ReceivedRequest {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+async fn write_response(stream: &mut TcpStream, response: FakeResponse) -> std::io::Result<()> {
+    let reason = reqwest::StatusCode::from_u16(response.status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or("Unknown");
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
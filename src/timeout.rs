@@ -0,0 +1,180 @@
+use anyhow::Result;
+use std::fmt;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long each kind of call to the outside world, and a whole run, may take
+#[derive(Debug, Clone)]
+pub struct TimeoutSettings {
+    /// Each call to the conductor's admin interface
+    pub conductor: Duration,
+    /// Each zome call to hha and holofuel through the core app
+    pub hha: Duration,
+    /// Each request to hbs, hpos-api and the joining-code service
+    pub http: Duration,
+    /// Each happ bundle download
+    pub download: Duration,
+    /// A whole reconciliation run. Actions not started before the deadline are reported as timed out.
+    pub run: Duration,
+}
+
+impl Default for TimeoutSettings {
+    fn default() -> Self {
+        TimeoutSettings {
+            conductor: Duration::from_secs(30),
+            hha: Duration::from_secs(60),
+            http: Duration::from_secs(60),
+            download: Duration::from_secs(300),
+            run: Duration::from_secs(1800),
+        }
+    }
+}
+
+/// An operation that did not finish in the time it was given
+#[derive(Debug)]
+pub struct TimedOut {
+    pub operation: String,
+    pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out after {:?}", self.operation, self.after)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Fails with `TimedOut` if `future` does not finish within `after`
+pub async fn with_timeout<T>(
+    operation: &str,
+    after: Duration,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match tokio::time::timeout(after, future).await {
+        Ok(result) => result,
+        Err(_) => Err(TimedOut {
+            operation: operation.to_string(),
+            after,
+        }
+        .into()),
+    }
+}
+
+/// The point in time by which a whole run must be done
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    at: Instant,
+    run_timeout: Duration,
+}
+
+impl Deadline {
+    /// A deadline `run_timeout` from now
+    pub fn after(run_timeout: Duration) -> Self {
+        Deadline {
+            at: Instant::now() + run_timeout,
+            run_timeout,
+        }
+    }
+
+    pub fn has_passed(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// The error reported for an operation cut short, or never started, because the run took too long
+    pub fn exceeded(&self) -> anyhow::Error {
+        TimedOut {
+            operation: "reconciliation run".to_string(),
+            after: self.run_timeout,
+        }
+        .into()
+    }
+
+    /// Runs `future` unless the deadline has passed, and gives up on it once the deadline passes
+    pub async fn run<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.has_passed() {
+            return Err(self.exceeded());
+        }
+        match tokio::time::timeout_at(self.at, future).await {
+            Ok(result) => result,
+            Err(_) => Err(self.exceeded()),
+        }
+    }
+}
+
+/// Whether `error` was caused by an operation timing out, rather than failing
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<TimedOut>()
+            || cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_timeout())
+            || cause
+                .downcast_ref::<isahc::Error>()
+                .is_some_and(|e| e.is_timeout())
+            || cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == ErrorKind::TimedOut)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn operations_that_take_too_long_fail_with_timed_out() {
+        let result = with_timeout("list_apps", Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        })
+        .await;
+        let error = result.unwrap_err();
+        assert!(is_timeout(&error));
+        assert_eq!(error.to_string(), "list_apps timed out after 10ms");
+
+        let result = with_timeout("list_apps", Duration::from_secs(5), async { Ok(1) }).await;
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn a_deadline_cuts_short_running_operations_and_refuses_new_ones() {
+        let deadline = Deadline::after(Duration::from_millis(50));
+        assert!(!deadline.has_passed());
+        assert_eq!(deadline.run(async { Ok(1) }).await.unwrap(), 1);
+
+        let started = Instant::now();
+        let error = deadline
+            .run(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(is_timeout(&error));
+        assert_eq!(error.to_string(), "reconciliation run timed out after 50ms");
+
+        assert!(deadline.has_passed());
+        let mut started_after_deadline = false;
+        let error = deadline
+            .run(async {
+                started_after_deadline = true;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(is_timeout(&error));
+        assert!(!started_after_deadline);
+    }
+
+    #[test]
+    fn only_timeouts_are_timeouts() {
+        let io_error = |kind: ErrorKind| anyhow::Error::from(std::io::Error::from(kind));
+        assert!(is_timeout(&io_error(ErrorKind::TimedOut)));
+        assert!(!is_timeout(&io_error(ErrorKind::ConnectionReset)));
+        assert!(!is_timeout(&anyhow::anyhow!("request timed out")));
+    }
+}
@@ -1,9 +1,11 @@
-use holochain_types::prelude::MembraneProof;
+use super::hbs::KycLevel;
+use holochain_types::prelude::{AgentPubKey, MembraneProof};
 use holofuel_types::fuel::Fuel;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
 use std::time::Duration;
 use tracing::warn;
 
@@ -13,6 +15,34 @@ pub struct InstallHappBody {
     pub membrane_proofs: HashMap<String, MembraneProof>,
 }
 
+/// hpos-api's reply to `InstallHappBody`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallHappResponse {
+    Installed,
+    Rejected { status: u16, message: String },
+}
+
+/// The JSON body hpos-api replies with when a request fails
+#[derive(Deserialize, Debug)]
+struct ApiErrorBody {
+    #[serde(alias = "message")]
+    error: String,
+}
+
+impl InstallHappResponse {
+    /// Parses the reply from its status and body, falling back to the raw body as the error message
+    pub fn parse(status: u16, body: &str) -> Self {
+        if (200..300).contains(&status) {
+            return InstallHappResponse::Installed;
+        }
+        let message = match serde_json::from_str::<ApiErrorBody>(body) {
+            Ok(ApiErrorBody { error }) => error,
+            Err(_) => body.trim().to_string(),
+        };
+        InstallHappResponse::Rejected { status, message }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HappPreferences {
     pub max_fuel_before_invoice: Fuel,
@@ -63,6 +93,29 @@ impl HappPreferences {
         true
     }
 
+    /// The resources the happ pays less for than the host charges
+    pub fn resources_priced_below_host(&self, happ_pricing: &HappPricing) -> Vec<PricedResource> {
+        PricedResource::ALL
+            .into_iter()
+            .filter(|resource| happ_pricing.price(*resource) < self.price(*resource))
+            .collect()
+    }
+
+    /// Whether the happ's publisher agrees to be invoiced at least as often as the host invoices,
+    /// both by the fuel owed and by the time since the last invoice
+    pub fn are_valid_invoice_terms(&self, happ_pricing: &HappPricing) -> bool {
+        happ_pricing.max_fuel_before_invoice <= self.max_fuel_before_invoice
+            && happ_pricing.max_time_before_invoice <= self.max_time_before_invoice
+    }
+
+    pub fn price(&self, resource: PricedResource) -> &Fuel {
+        match resource {
+            PricedResource::Compute => &self.price_compute,
+            PricedResource::Storage => &self.price_storage,
+            PricedResource::Bandwidth => &self.price_bandwidth,
+        }
+    }
+
     pub fn is_happ_valid_category(
         &self, // host preferences
         happ_categories: &[String],
@@ -95,6 +148,108 @@ impl HappPreferences {
     }
 }
 
+/// A resource a host charges for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PricedResource {
+    Compute,
+    Storage,
+    Bandwidth,
+}
+
+impl PricedResource {
+    pub const ALL: [PricedResource; 3] = [
+        PricedResource::Compute,
+        PricedResource::Storage,
+        PricedResource::Bandwidth,
+    ];
+}
+
+impl fmt::Display for PricedResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PricedResource::Compute => "compute",
+            PricedResource::Storage => "storage",
+            PricedResource::Bandwidth => "bandwidth",
+        })
+    }
+}
+
+/// The prices and invoicing terms a publisher set for hosting their happ in hha
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HappPricing {
+    pub price_compute: Fuel,
+    pub price_storage: Fuel,
+    pub price_bandwidth: Fuel,
+    pub max_fuel_before_invoice: Fuel,
+    pub max_time_before_invoice: Duration,
+}
+
+impl HappPricing {
+    /// Pricing that exactly meets the host's terms
+    pub fn matching(host_preferences: &HappPreferences) -> Self {
+        HappPricing {
+            price_compute: host_preferences.price_compute.clone(),
+            price_storage: host_preferences.price_storage.clone(),
+            price_bandwidth: host_preferences.price_bandwidth.clone(),
+            max_fuel_before_invoice: host_preferences.max_fuel_before_invoice.clone(),
+            max_time_before_invoice: host_preferences.max_time_before_invoice,
+        }
+    }
+
+    pub fn price(&self, resource: PricedResource) -> &Fuel {
+        match resource {
+            PricedResource::Compute => &self.price_compute,
+            PricedResource::Storage => &self.price_storage,
+            PricedResource::Bandwidth => &self.price_bandwidth,
+        }
+    }
+}
+
+/// The most a happ may pay per unit of any resource to a host of each kyc level, unlimited where unset
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct KycPriceLimits {
+    pub level_1: Option<Fuel>,
+    pub level_2: Option<Fuel>,
+}
+
+impl KycPriceLimits {
+    pub fn max_price(&self, level: &KycLevel) -> Option<&Fuel> {
+        match level {
+            KycLevel::Level1 => self.level_1.as_ref(),
+            KycLevel::Level2 => self.level_2.as_ref(),
+        }
+    }
+}
+
+/// What a publisher registered for their happ in hha: who they are, and what they pay for hosting it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedHappPreferences {
+    pub publisher: AgentPubKey,
+    pub pricing: HappPricing,
+}
+
+impl From<hpos_hc_connect::hha_types::ServiceloggerHappPreferences> for PublishedHappPreferences {
+    fn from(value: hpos_hc_connect::hha_types::ServiceloggerHappPreferences) -> Self {
+        PublishedHappPreferences {
+            publisher: value.provider_pubkey.clone(),
+            pricing: value.into(),
+        }
+    }
+}
+
+impl From<hpos_hc_connect::hha_types::ServiceloggerHappPreferences> for HappPricing {
+    fn from(value: hpos_hc_connect::hha_types::ServiceloggerHappPreferences) -> Self {
+        HappPricing {
+            price_compute: value.price_compute,
+            price_storage: value.price_storage,
+            price_bandwidth: value.price_bandwidth,
+            max_fuel_before_invoice: value.max_fuel_before_invoice,
+            max_time_before_invoice: value.max_time_before_invoice,
+        }
+    }
+}
+
 impl From<hpos_hc_connect::hha_types::HappPreferences> for HappPreferences {
     fn from(value: hpos_hc_connect::hha_types::HappPreferences) -> Self {
         HappPreferences {
@@ -133,3 +288,41 @@ impl From<hpos_hc_connect::hha_types::ExclusivePreferences> for ExclusivePrefere
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_replies_are_parsed_by_status_and_error_body() {
+        assert_eq!(
+            InstallHappResponse::parse(200, "{}"),
+            InstallHappResponse::Installed
+        );
+        assert_eq!(
+            InstallHappResponse::parse(204, ""),
+            InstallHappResponse::Installed
+        );
+        assert_eq!(
+            InstallHappResponse::parse(500, r#"{"error": "conductor unreachable"}"#),
+            InstallHappResponse::Rejected {
+                status: 500,
+                message: "conductor unreachable".to_string()
+            }
+        );
+        assert_eq!(
+            InstallHappResponse::parse(400, r#"{"message": "invalid membrane proof"}"#),
+            InstallHappResponse::Rejected {
+                status: 400,
+                message: "invalid membrane proof".to_string()
+            }
+        );
+        assert_eq!(
+            InstallHappResponse::parse(502, " Bad Gateway\n"),
+            InstallHappResponse::Rejected {
+                status: 502,
+                message: "Bad Gateway".to_string()
+            }
+        );
+    }
+}
@@ -1,12 +1,17 @@
+use crate::retry::{HttpStatusError, RetrySettings};
+use crate::timeout::TimeoutSettings;
+use crate::utils::load_hpos_config;
 use anyhow::Context;
 use anyhow::Result;
 use base64::prelude::*;
 use holochain_types::prelude::{holochain_serial, SerializedBytes, Signature, Timestamp};
 use hpos_hc_connect::hha_agent::CoreAppAgent;
-use hpos_hc_connect::hpos_agent::get_hpos_config;
 use reqwest::Response;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
 use tracing::warn;
+use url::Url;
 
 const MATTERMOST_NOTIFICATION_CHANNEL: &str = "rgf8oe3843r5xehhp66q58onfa";
 
@@ -34,6 +39,9 @@ pub struct HostCredentials {
     pub jurisdiction: Option<String>,
     #[serde(default)]
     pub kyc: KycLevel,
+    /// Fraction of time (0 to 1) the holoport was measured to be online, if known
+    #[serde(default)]
+    pub uptime: Option<f32>,
     // The following is also returned by this hbs endpoint:
     // pub publicKey: Option<String>,
     // pub email: String,
@@ -60,6 +68,17 @@ impl HostCredentials {
             happ_jurisdictions.contains(&host_jurisdiction)
         }
     }
+
+    /// A host whose uptime is unknown only meets happs that require none, like a host without a jurisdiction
+    pub fn is_host_meeting_uptime(&self, min_uptime: f32) -> bool {
+        match self.uptime {
+            Some(uptime) => uptime >= min_uptime,
+            None => {
+                warn!("Host's uptime not available");
+                min_uptime <= 0.0
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
@@ -73,11 +92,43 @@ pub enum KycLevel {
 
 pub struct HbsClient {
     pub client: reqwest::Client,
+    pub retry: RetrySettings,
+    /// How long each request to hbs, including signing it with the host agent key, may take
+    pub timeout: Duration,
+    /// Base url of hbs, read from the `HBS_URL` env var if unset
+    pub url: Option<Url>,
+    /// hpos config file the host's email is read from, read from the `HPOS_CONFIG_PATH` env var if unset
+    pub hpos_config_path: Option<PathBuf>,
 }
 impl HbsClient {
     pub fn connect() -> Result<Self> {
         let client = reqwest::Client::builder().build()?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            retry: RetrySettings::default(),
+            timeout: TimeoutSettings::default().http,
+            url: None,
+            hpos_config_path: None,
+        })
+    }
+
+    pub fn with_retry(self, retry: RetrySettings) -> Self {
+        Self { retry, ..self }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn with_url(self, url: Option<Url>) -> Self {
+        Self { url, ..self }
+    }
+
+    pub fn with_hpos_config_path(self, hpos_config_path: Option<PathBuf>) -> Self {
+        Self {
+            hpos_config_path,
+            ..self
+        }
     }
     pub async fn get_host_hosting_criteria(&self) -> Option<HostCredentials> {
         match self.get_access_token().await {
@@ -112,9 +163,10 @@ impl HbsClient {
             .client
             .request(
                 reqwest::Method::POST,
-                format!("{}/ops/api/v1/mattermost/notify", hbs_url()?),
+                format!("{}/ops/api/v1/mattermost/notify", self.hbs_url()?),
             )
             .headers(headers)
+            .timeout(self.timeout)
             .json(&json);
         if let Err(err) = request.send().await {
             tracing::error!("failed to send notification to mattermost: {:?}", err);
@@ -124,21 +176,31 @@ impl HbsClient {
     }
 
     async fn get_access_token(&self) -> Result<Option<HostCredentials>> {
-        let response = self.inner_get_access_token().await?;
-        tracing::debug!("response received");
-        let mut body = response.text().await?;
-
-        // 504 Gateway Timeout
-        // here we either need to retry once more or end the script
-        if body.contains("error code: 504") {
-            tracing::warn!("Gateway Timeout. Retrying once more...");
-            let response = self.inner_get_access_token().await?;
-            body = response.text().await?;
-            if body.contains("error code: 504") {
+        let body = match self
+            .retry
+            .retry_with_timeout("hbs authentication", self.timeout, move || async move {
+                let response = self.inner_get_access_token().await?;
+                tracing::debug!("response received");
+                let body = response.text().await?;
+                // 504 Gateway Timeout
+                if body.contains("error code: 504") {
+                    return Err(HttpStatusError {
+                        status: 504,
+                        message: "Gateway Timeout".to_string(),
+                    }
+                    .into());
+                }
+                Ok(body)
+            })
+            .await
+        {
+            Ok(body) => body,
+            Err(e) if e.downcast_ref::<HttpStatusError>().is_some() => {
                 tracing::warn!("Gateway Timeout. Exiting...");
                 return Ok(None);
             }
-        }
+            Err(e) => return Err(e),
+        };
 
         tracing::debug!("Result: {}", body);
         let result: serde_json::Value = serde_json::from_str(&body)?;
@@ -148,7 +210,7 @@ impl HbsClient {
     }
 
     async fn inner_get_access_token(&self) -> Result<Response> {
-        let config: hpos_config_core::Config = get_hpos_config()?;
+        let config = load_hpos_config(self.hpos_config_path.as_deref())?;
 
         let email = config.email();
 
@@ -182,15 +244,18 @@ impl HbsClient {
             .client
             .request(
                 reqwest::Method::POST,
-                format!("{}/auth/api/v1/holo-client", hbs_url()?),
+                format!("{}/auth/api/v1/holo-client", self.hbs_url()?),
             )
             .headers(headers)
             .json(&json);
 
         Ok(request.send().await?)
     }
-}
 
-fn hbs_url() -> Result<String> {
-    std::env::var("HBS_URL").context("Failed to read HBS_URL. Is it set in env?")
+    fn hbs_url(&self) -> Result<String> {
+        match &self.url {
+            Some(url) => Ok(url.as_str().trim_end_matches('/').to_string()),
+            None => std::env::var("HBS_URL").context("Failed to read HBS_URL. Is it set in env?"),
+        }
+    }
 }
@@ -1,6 +1,7 @@
 pub mod happ;
 pub mod hbs;
 pub mod transaction;
+use happ::HappPricing;
 use holochain_types::dna::ActionHashB64;
 use holochain_types::prelude::{holochain_serial, SerializedBytes};
 use hpos_hc_connect::hha_types::HostSettings;
@@ -16,6 +17,10 @@ pub struct HappBundle {
     pub exclude_jurisdictions: bool,
     pub categories: Vec<String>,
     pub host_settings: HostSettings,
+    /// Hashes of the happ's DNAs as registered in hha
+    pub dna_hashes: Vec<String>,
+    /// Fraction of time (0 to 1) a holoport must be online to host the happ, as registered in hha
+    pub min_uptime: f32,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, SerializedBytes)]
@@ -25,4 +30,8 @@ pub struct PublishedHappDetails {
     pub should_exclude_happ_jurisdictions: bool,
     pub happ_categories: Vec<String>,
     pub is_disabled_by_host: bool,
+    /// The prices and invoicing terms the publisher set for the happ
+    pub happ_pricing: HappPricing,
+    /// Fraction of time (0 to 1) a holoport must be online to host the happ
+    pub min_uptime: f32,
 }
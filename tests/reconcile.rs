@@ -0,0 +1,253 @@
+use holo_auto_installer::test_support::{
+    fake_happ, fake_happ_id, fake_host_credentials, fake_host_preferences, fuel,
+    no_pending_transactions, FakeConductor, FakeHostBackend, FakeHostingRegistry, FakeInstaller,
+};
+use holo_auto_installer::types::happ::{HappPricing, PricedResource};
+use holo_auto_installer::{
+    plan_reconciliation, reconcile, take_snapshot, BundleCache, HappAction, Ineligibility,
+    InstallerState, Settings, StateStore, StopSignal,
+};
+use holochain_types::prelude::AgentPubKey;
+
+#[tokio::test(flavor = "current_thread")]
+async fn reconcile_installs_eligible_happs_and_removes_the_rest() {
+    let eligible = fake_happ_id(1).to_string();
+    let underpriced = fake_happ_id(2).to_string();
+    let unpublished = fake_happ_id(3).to_string();
+    let on_core_app = fake_happ_id(4).to_string();
+
+    let publisher = AgentPubKey::from_raw_36(vec![9; 36]);
+    let mut registry = FakeHostingRegistry::new(fake_host_preferences(), no_pending_transactions());
+    registry.publish(fake_happ(1), publisher.clone());
+    registry.publish_with_pricing(
+        fake_happ(2),
+        publisher.clone(),
+        HappPricing {
+            price_storage: fuel("0.5"),
+            ..HappPricing::matching(&fake_host_preferences())
+        },
+    );
+    let mut core_app_happ = fake_happ(4);
+    core_app_happ.special_installed_app_id = Some("core-app:0_2_1".to_string());
+    registry.publish(core_app_happ, publisher);
+
+    let mut conductor = FakeConductor::with_enabled_apps([
+        "core-app:0_2_1".to_string(),
+        underpriced.clone(),
+        format!("{}::agent", underpriced),
+        format!("{}::agent", unpublished),
+    ]);
+    let installer = FakeInstaller::new(&conductor);
+    let host = FakeHostBackend {
+        host_credentials: fake_host_credentials(),
+    };
+    let cache_dir = tempfile::tempdir().expect("temp dir");
+    let cache = BundleCache::new(cache_dir.path().to_path_buf(), 1024 * 1024);
+    let mut state = StateStore::in_memory();
+
+    let report = reconcile(
+        &host,
+        &mut registry,
+        &mut conductor,
+        &installer,
+        &cache,
+        &mut state,
+        &Settings::default(),
+        &StopSignal::default(),
+    )
+    .await
+    .expect("reconcile runs");
+
+    let mut installed = installer.installed();
+    installed.sort();
+    assert_eq!(installed, vec![eligible.clone(), on_core_app.clone()]);
+
+    let conductor_state = conductor.state().clone();
+    let mut enabled = conductor_state.enabled;
+    enabled.sort();
+    let mut expected_enabled = vec![
+        "core-app:0_2_1".to_string(),
+        eligible.clone(),
+        format!("{}::servicelogger", eligible),
+        format!("{}::servicelogger", on_core_app),
+    ];
+    expected_enabled.sort();
+    assert_eq!(enabled, expected_enabled);
+    // The anonymous instance is only disabled, identified instances are uninstalled
+    assert_eq!(conductor_state.disabled, vec![underpriced.clone()]);
+    assert_eq!(
+        conductor_state.uninstalled,
+        vec![
+            format!("{}::agent", underpriced),
+            format!("{}::agent", unpublished),
+        ]
+    );
+    assert_eq!(registry.holo_disabled, vec![underpriced.clone()]);
+    assert!(registry.holo_enabled.is_empty());
+
+    assert!(!report.has_failures());
+    let applied: Vec<(&str, &HappAction)> = report
+        .actions
+        .iter()
+        .map(|outcome| {
+            (
+                outcome.action.installed_app_id.as_str(),
+                &outcome.action.action,
+            )
+        })
+        .filter(|(_, action)| !matches!(action, HappAction::Install { .. }))
+        .collect();
+    let underpriced_agent = format!("{}::agent", underpriced);
+    let unpublished_agent = format!("{}::agent", unpublished);
+    assert_eq!(
+        applied,
+        vec![
+            (underpriced.as_str(), &HappAction::Disable),
+            (underpriced_agent.as_str(), &HappAction::Uninstall),
+            (unpublished_agent.as_str(), &HappAction::Uninstall),
+            (underpriced.as_str(), &HappAction::HoloDisable),
+        ]
+    );
+
+    let verdicts: Vec<(&str, &[Ineligibility])> = report
+        .happs
+        .iter()
+        .map(|happ| (happ.happ_id.as_str(), happ.ineligibility.as_slice()))
+        .collect();
+    assert_eq!(
+        verdicts,
+        vec![
+            (eligible.as_str(), &[][..]),
+            (
+                underpriced.as_str(),
+                &[Ineligibility::PriceBelowHostMinimum {
+                    resource: PricedResource::Storage,
+                    happ_price: fuel("0.5"),
+                    host_price: fuel("1"),
+                }][..]
+            ),
+            (on_core_app.as_str(), &[][..]),
+        ]
+    );
+    assert!(state.state().apps.contains_key(&unpublished_agent));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn a_dry_run_lists_every_action_without_taking_any() {
+    let eligible = fake_happ_id(1).to_string();
+    let underpriced = fake_happ_id(2).to_string();
+    let underpriced_agent = format!("{}::agent", underpriced);
+
+    let publisher = AgentPubKey::from_raw_36(vec![9; 36]);
+    let mut registry = FakeHostingRegistry::new(fake_host_preferences(), no_pending_transactions());
+    registry.publish(fake_happ(1), publisher.clone());
+    registry.publish_with_pricing(
+        fake_happ(2),
+        publisher,
+        HappPricing {
+            price_storage: fuel("0.5"),
+            ..HappPricing::matching(&fake_host_preferences())
+        },
+    );
+    let mut conductor =
+        FakeConductor::with_enabled_apps([underpriced.clone(), underpriced_agent.clone()]);
+    let installer = FakeInstaller::new(&conductor);
+    let host = FakeHostBackend {
+        host_credentials: fake_host_credentials(),
+    };
+
+    let snapshot = take_snapshot(
+        &host,
+        &mut registry,
+        &mut conductor,
+        &InstallerState::default(),
+        &Settings::default(),
+    )
+    .await
+    .expect("snapshot is taken");
+    let printed = plan_reconciliation(&snapshot).to_string();
+
+    let expected_prefixes = [
+        format!("install      {} (", eligible),
+        format!("disable      {} (", underpriced),
+        format!("uninstall    {} (", underpriced_agent),
+        format!("holo-disable {} (", underpriced),
+    ];
+    let lines: Vec<&str> = printed.lines().collect();
+    assert_eq!(lines.len(), expected_prefixes.len(), "{}", printed);
+    for (line, prefix) in lines.iter().zip(expected_prefixes.iter()) {
+        assert!(line.starts_with(prefix.as_str()), "{}", printed);
+    }
+
+    assert!(installer.installed().is_empty());
+    let conductor_state = conductor.state().clone();
+    assert_eq!(
+        conductor_state.enabled,
+        vec![underpriced.clone(), underpriced_agent]
+    );
+    assert!(conductor_state.disabled.is_empty());
+    assert!(conductor_state.uninstalled.is_empty());
+    assert!(registry.holo_disabled.is_empty());
+    assert!(registry.holo_enabled.is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn a_second_reconcile_changes_nothing() {
+    let underpriced = fake_happ_id(2).to_string();
+
+    let publisher = AgentPubKey::from_raw_36(vec![9; 36]);
+    let mut registry = FakeHostingRegistry::new(fake_host_preferences(), no_pending_transactions());
+    registry.publish(fake_happ(1), publisher.clone());
+    registry.publish_with_pricing(
+        fake_happ(2),
+        publisher,
+        HappPricing {
+            price_storage: fuel("0.5"),
+            ..HappPricing::matching(&fake_host_preferences())
+        },
+    );
+    let mut conductor = FakeConductor::with_enabled_apps([
+        underpriced.clone(),
+        format!("{}::agent", underpriced),
+        format!("{}::agent", fake_happ_id(3)),
+    ]);
+    let installer = FakeInstaller::new(&conductor);
+    let host = FakeHostBackend {
+        host_credentials: fake_host_credentials(),
+    };
+    let cache_dir = tempfile::tempdir().expect("temp dir");
+    let cache = BundleCache::new(cache_dir.path().to_path_buf(), 1024 * 1024);
+    let mut state = StateStore::in_memory();
+
+    let first = reconcile(
+        &host,
+        &mut registry,
+        &mut conductor,
+        &installer,
+        &cache,
+        &mut state,
+        &Settings::default(),
+        &StopSignal::default(),
+    )
+    .await
+    .expect("first reconcile runs");
+    assert!(!first.actions.is_empty());
+    let conductor_after_first = conductor.state().clone();
+
+    let second = reconcile(
+        &host,
+        &mut registry,
+        &mut conductor,
+        &installer,
+        &cache,
+        &mut state,
+        &Settings::default(),
+        &StopSignal::default(),
+    )
+    .await
+    .expect("second reconcile runs");
+    assert!(second.actions.is_empty(), "{:?}", second.actions);
+    assert_eq!(*conductor.state(), conductor_after_first);
+    assert_eq!(installer.installed(), vec![fake_happ_id(1).to_string()]);
+    assert_eq!(registry.holo_disabled, vec![underpriced]);
+}